@@ -2,7 +2,7 @@
 
 #![allow(non_snake_case, non_upper_case_globals)]
 
-use std::ffi::CString;
+use std::ffi::{CStr, CString};
 use std::fmt;
 use std::num::ParseIntError;
 use std::os::raw::*;
@@ -226,6 +226,8 @@ pub static DrawCrosshair: Pointer<unsafe extern "C" fn(c_int, c_int)> = Pointer:
     ]),
     my_DrawCrosshair as _,
 );
+pub static Draw_String: Pointer<unsafe extern "C" fn(c_int, c_int, *mut c_char) -> c_int> =
+    Pointer::empty(b"Draw_String\0");
 pub static frametime_remainder: Pointer<*mut f64> = Pointer::empty(
     // Not a real symbol name.
     b"frametime_remainder\0",
@@ -698,6 +700,11 @@ pub static Z_Free: Pointer<unsafe extern "C" fn(*mut c_void)> = Pointer::empty_p
 );
 
 pub static client_s_edict_offset: MainThreadCell<Option<usize>> = MainThreadCell::new(None);
+/// Offset of the `paused` field in `server_t`, which depends on the engine build.
+///
+/// Set along with `sv` for the builds with a known `server_t` layout. Without it, whether the
+/// server is paused is unknown.
+pub static sv_paused_offset: MainThreadCell<Option<usize>> = MainThreadCell::new(None);
 
 static POINTERS: &[&dyn PointerTrait] = &[
     &build_number,
@@ -725,6 +732,7 @@ static POINTERS: &[&dyn PointerTrait] = &[
     &Cvar_RegisterVariable,
     &cvar_vars,
     &DrawCrosshair,
+    &Draw_String,
     &frametime_remainder,
     &GL_BeginRendering,
     &gEntityInterface,
@@ -989,6 +997,107 @@ pub unsafe fn get_resolution(marker: MainThreadMarker) -> (i32, i32) {
     }
 }
 
+/// Returns the console variable with the given name.
+///
+/// If the variable doesn't exist, returns `None`.
+///
+/// # Panics
+///
+/// Panics if `cvar_vars` was not found.
+pub unsafe fn find_cvar(marker: MainThreadMarker, name: &str) -> Option<NonNull<cvar_s>> {
+    let mut ptr = *cvar_vars.get(marker);
+    while !ptr.is_null() {
+        match CStr::from_ptr((*ptr).name).to_str() {
+            Ok(x) if x == name => {
+                return NonNull::new(ptr);
+            }
+            _ => (),
+        }
+
+        ptr = (*ptr).next;
+    }
+
+    None
+}
+
+/// Returns the value of the console variable with the given name.
+///
+/// If the variable doesn't exist, returns `None`.
+///
+/// # Panics
+///
+/// Panics if `cvar_vars` was not found.
+pub unsafe fn get_cvar_f32(marker: MainThreadMarker, name: &str) -> Option<f32> {
+    match find_cvar(marker, name) {
+        Some(cvar) => Some(cvar.as_ref().value),
+        None => {
+            warn!("couldn't find cvar {}", name);
+            None
+        }
+    }
+}
+
+/// Returns the name of the current map and the current server time.
+///
+/// If the server is not active or the required pointers are missing, returns `None`.
+pub unsafe fn server_map_and_time(marker: MainThreadMarker) -> Option<(String, f64)> {
+    // Doubles in server_t are aligned to 4 bytes on Linux and to 8 bytes on Windows.
+    #[cfg(unix)]
+    const TIME_OFFSET: usize = 12;
+    #[cfg(windows)]
+    const TIME_OFFSET: usize = 16;
+    #[cfg(unix)]
+    const NAME_OFFSET: usize = 40;
+    #[cfg(windows)]
+    const NAME_OFFSET: usize = 48;
+
+    let sv_ = sv.get_opt(marker)?.cast::<u8>();
+    let active = *sv_.cast::<c_int>();
+    if active == 0 {
+        return None;
+    }
+
+    let time = sv_.add(TIME_OFFSET).cast::<f64>().read_unaligned();
+    let name = CStr::from_ptr(sv_.add(NAME_OFFSET).cast()).to_str().ok()?;
+    if name.is_empty() {
+        return None;
+    }
+
+    Some((name.to_owned(), time))
+}
+
+/// Returns whether the server is active, that is, running a map.
+///
+/// Returns `None` if the server wasn't found.
+///
+/// # Safety
+///
+/// `sv` must be valid to read from.
+pub unsafe fn sv_active(marker: MainThreadMarker) -> Option<bool> {
+    let sv_ = sv.get_opt(marker)?;
+    Some(*sv_.cast::<c_int>() != 0)
+}
+
+/// Returns whether the server is paused.
+///
+/// Returns `None` if the server or the offset of its `paused` field wasn't found.
+///
+/// # Safety
+///
+/// `sv` must be valid to read from.
+pub unsafe fn sv_paused(marker: MainThreadMarker) -> Option<bool> {
+    let offset = sv_paused_offset.get(marker)?;
+    let sv_ = sv.get_opt(marker)?.cast::<u8>();
+    Some(*sv_.add(offset).cast::<c_int>() != 0)
+}
+
+/// Warns if `sv` was found, but the offset of its `paused` field isn't known for this build.
+fn warn_if_sv_paused_offset_unknown(marker: MainThreadMarker) {
+    if sv.is_set(marker) && sv_paused_offset.get(marker).is_none() {
+        warn!("the offset of sv.paused is unknown for this engine build, pauses can't be detected");
+    }
+}
+
 pub unsafe fn player_edict(marker: MainThreadMarker) -> Option<NonNull<edict_s>> {
     // SAFETY: we're not calling any engine functions while the reference is alive.
     let offset = client_s_edict_offset.get(marker)?;
@@ -1039,10 +1148,15 @@ unsafe fn find_pointers(marker: MainThreadMarker) {
     ran1_iy.set(marker, ran1.by_offset(marker, 13));
     ran1_iv.set(marker, ran1.by_offset(marker, 116));
     client_s_edict_offset.set(marker, Some(19076));
+    if sv.is_set(marker) {
+        // The Linux builds start `server_t` with `active` followed by `paused`.
+        sv_paused_offset.set(marker, Some(4));
+    }
 
     for pointer in POINTERS {
         pointer.log(marker);
     }
+    warn_if_sv_paused_offset_unknown(marker);
 }
 
 /// # Safety
@@ -1189,6 +1303,8 @@ pub unsafe fn find_pointers(marker: MainThreadMarker, base: *mut c_void, size: u
         // 6153
         Some(0) => {
             sv.set(marker, ptr.by_offset(marker, 19));
+            // `server_t` in 6153 starts with `active` followed by `paused`.
+            sv_paused_offset.set(marker, Some(4));
             cls.set(marker, ptr.by_offset(marker, 69));
             Con_Printf.set_if_empty(marker, ptr.by_relative_call(marker, 33));
         }
@@ -1311,6 +1427,8 @@ pub unsafe fn find_pointers(marker: MainThreadMarker, base: *mut c_void, size: u
         // 6153
         Some(0) => {
             sv.set(marker, ptr.by_offset(marker, 1));
+            // `server_t` in 6153 starts with `active` followed by `paused`.
+            sv_paused_offset.set(marker, Some(4));
             host_frametime.set(marker, ptr.by_offset(marker, 11));
         }
         _ => (),
@@ -1352,6 +1470,8 @@ pub unsafe fn find_pointers(marker: MainThreadMarker, base: *mut c_void, size: u
         "{:?}: client_s_edict_offset",
         client_s_edict_offset.get(marker)
     );
+    debug!("{:?}: sv_paused_offset", sv_paused_offset.get(marker));
+    warn_if_sv_paused_offset_unknown(marker);
 
     // Hook only Memory_Init() and the rest later, for BXT compatibility.
     maybe_hook(marker, &Memory_Init);
@@ -1394,6 +1514,7 @@ fn reset_pointers(marker: MainThreadMarker) {
     for pointer in POINTERS {
         pointer.reset(marker);
     }
+    sv_paused_offset.set(marker, None);
 
     // Remove all hooks.
     #[cfg(windows)]
@@ -1733,6 +1854,10 @@ pub mod exported {
         abort_on_panic(move || {
             let marker = MainThreadMarker::new();
 
+            if tas_recording::on_host_next_demo(marker) {
+                return;
+            }
+
             Host_NextDemo.get(marker)();
 
             demo_playback::set_next_demo(marker);
@@ -1781,6 +1906,8 @@ pub mod exported {
         abort_on_panic(move || {
             let marker = MainThreadMarker::new();
 
+            tas_recording::on_demo_update_client_data(marker, cdat);
+
             hud_scale::with_scaled_screen_info(marker, move || {
                 ClientDLL_DemoUpdateClientData.get(marker)(cdat)
             });
@@ -1806,6 +1933,8 @@ pub mod exported {
             hud_scale::with_scaled_projection_matrix(marker, move || {
                 ClientDLL_HudRedraw.get(marker)(intermission)
             });
+
+            tas_recording::on_hud_redraw(marker);
         })
     }
 
@@ -1853,6 +1982,8 @@ pub mod exported {
 
             let text = comment_overflow_fix::strip_prefix_comments(text);
 
+            tas_recording::on_cbuf_addtext(marker, text);
+
             Cbuf_AddFilteredText.get(marker)(text);
         })
     }
@@ -1,6 +1,7 @@
 //! Video capture.
 
-use std::mem;
+use std::path::PathBuf;
+use std::{fs, mem};
 
 use color_eyre::eyre::Context;
 
@@ -22,7 +23,7 @@ impl Module for Capture {
     }
 
     fn commands(&self) -> &'static [&'static Command] {
-        static COMMANDS: &[&Command] = &[&BXT_CAP_START, &BXT_CAP_STOP];
+        static COMMANDS: &[&Command] = &[&BXT_CAP_START, &BXT_CAP_START_IMAGES, &BXT_CAP_STOP];
         COMMANDS
     }
 
@@ -31,6 +32,9 @@ impl Module for Capture {
             &BXT_CAP_FPS,
             &BXT_CAP_VOLUME,
             &BXT_CAP_SOUND_EXTRA,
+            &BXT_CAP_AUDIO,
+            &BXT_CAP_IMAGES_PADDING,
+            &BXT_CAP_IMAGES_START,
             &BXT_CAP_SLOWDOWN,
             &BXT_CAP_FORCE_FALLBACK,
             &BXT_CAP_OVERRIDE_FFMPEG_ARGS,
@@ -56,6 +60,7 @@ impl Module for Capture {
 }
 
 mod muxer;
+use muxer::Output;
 mod opengl;
 mod recorder;
 use recorder::{CaptureType, Recorder};
@@ -76,6 +81,21 @@ static BXT_CAP_SOUND_EXTRA: CVar = CVar::new(
     b"0\0",
     "How many extra seconds of audio to mix and capture past the end of the recording.",
 );
+static BXT_CAP_AUDIO: CVar = CVar::new(
+    b"bxt_cap_audio\0",
+    b"1\0",
+    "Set to `0` to record videos without the audio track.",
+);
+static BXT_CAP_IMAGES_PADDING: CVar = CVar::new(
+    b"bxt_cap_images_padding\0",
+    b"6\0",
+    "Number of digits that `bxt_cap_start_images` pads the image numbers to with zeros.",
+);
+static BXT_CAP_IMAGES_START: CVar = CVar::new(
+    b"bxt_cap_images_start\0",
+    b"1\0",
+    "Number of the first image written by `bxt_cap_start_images`.",
+);
 static BXT_CAP_VOLUME: CVar = CVar::new(
     b"bxt_cap_volume\0",
     b"0.4\0",
@@ -174,7 +194,7 @@ pub enum SoundCaptureMode {
 #[allow(clippy::large_enum_variant)]
 enum State {
     Idle,
-    Starting(String),
+    Starting(Output),
     Recording(Recorder),
 }
 
@@ -210,13 +230,68 @@ fn cap_start_with_filename(marker: MainThreadMarker, filename: String) {
         return;
     }
 
+    if filename.ends_with(".wav") && !BXT_CAP_AUDIO.as_bool(marker) {
+        con_print(
+            marker,
+            "Error: can't capture only the sound with bxt_cap_audio set to 0.\n",
+        );
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    if !matches!(*state, State::Idle) {
+        // Already capturing.
+        return;
+    }
+
+    *state = State::Starting(Output::File(filename));
+}
+
+static BXT_CAP_START_IMAGES: Command = Command::new(
+    b"bxt_cap_start_images\0",
+    handler!(
+        "bxt_cap_start_images <directory>
+
+Starts capturing video frames as numbered PNG images, like `frame_000001.png`, into the directory. \
+The number padding and the first number are set by `bxt_cap_images_padding` and \
+`bxt_cap_images_start`.",
+        cap_start_images as fn(_, _)
+    ),
+);
+
+fn cap_start_images(marker: MainThreadMarker, dir: PathBuf) {
+    if !Capture.is_enabled(marker) {
+        return;
+    }
+
     let mut state = STATE.borrow_mut(marker);
     if !matches!(*state, State::Idle) {
         // Already capturing.
         return;
     }
 
-    *state = State::Starting(filename);
+    if let Err(err) = fs::create_dir_all(&dir) {
+        con_print(
+            marker,
+            &format!("Error creating the output directory: {}.\n", err),
+        );
+        return;
+    }
+
+    let padding = BXT_CAP_IMAGES_PADDING.as_u64(marker).clamp(1, 20);
+    let pattern = dir.join(format!("frame_%0{}d.png", padding));
+    let pattern = match pattern.into_os_string().into_string() {
+        Ok(pattern) => pattern,
+        Err(_) => {
+            con_print(marker, "Error: the directory name is not valid UTF-8.\n");
+            return;
+        }
+    };
+
+    *state = State::Starting(Output::Images {
+        pattern,
+        start_number: BXT_CAP_IMAGES_START.as_u64(marker),
+    });
 }
 
 static BXT_CAP_STOP: Command = Command::new(
@@ -277,7 +352,7 @@ pub unsafe fn capture_frame(marker: MainThreadMarker) {
     let (width, height) = engine::get_resolution(marker);
 
     // Initialize the recording if needed.
-    if let State::Starting(ref filename) = *state {
+    if let State::Starting(ref output) = *state {
         let fps = BXT_CAP_FPS.as_u64(marker).max(1);
         let slowdown = BXT_CAP_SLOWDOWN.as_f32(marker).max(0.1) as f64;
 
@@ -319,12 +394,18 @@ pub unsafe fn capture_frame(marker: MainThreadMarker) {
             fps,
             slowdown,
             capture_type,
-            filename,
+            output,
             custom_ffmpeg_args,
+            BXT_CAP_AUDIO.as_bool(marker),
         ) {
             Ok(recorder) => {
-                if matches!(recorder.capture_type(), CaptureType::ReadPixels) {
-                    con_print(marker, "Recording in slower fallback mode.\n");
+                match recorder.capture_type() {
+                    CaptureType::Vulkan(_) => info!("capturing with Vulkan"),
+                    CaptureType::ReadPixels => {
+                        // Either forced or the memory object and semaphore extensions are missing.
+                        info!("capturing with glReadPixels");
+                        con_print(marker, "Recording in slower fallback mode.\n");
+                    }
                 }
                 *state = State::Recording(recorder)
             }
@@ -35,6 +35,20 @@ impl PixelFormat {
     }
 }
 
+/// Where the encoded frames go.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// A video file, or an audio file for `.wav`.
+    File(String),
+    /// A numbered PNG image sequence.
+    Images {
+        /// FFmpeg image sequence pattern, like `frames/frame_%06d.png`.
+        pattern: String,
+        /// Number of the first image.
+        start_number: u64,
+    },
+}
+
 fn v<W: Write>(mut writer: W, mut value: u64) -> Result<(), io::Error> {
     let mut elements = [0; 10];
     let mut i = 10;
@@ -102,9 +116,13 @@ impl Muxer {
         height: u64,
         fps: u64,
         pixel_format: PixelFormat,
-        filename: &str,
+        output: &Output,
         custom_ffmpeg_args: Option<&[&str]>,
+        audio: bool,
     ) -> Result<Self, MuxerInitError> {
+        // Declared first so that `args` can borrow it.
+        let start_number;
+
         #[rustfmt::skip]
         let mut args = vec![
             "-loglevel", "error",
@@ -116,29 +134,56 @@ impl Muxer {
             args.extend_from_slice(&["-vf", "vflip"]);
         }
 
-        if let Some(custom_ffmpeg_args) = custom_ffmpeg_args {
-            args.extend_from_slice(custom_ffmpeg_args);
-        } else {
-            #[rustfmt::skip]
-            args.extend_from_slice(&[
-                "-c:v", "libx264",
-                "-crf", "15",
-                "-preset", "ultrafast",
-                "-color_primaries", "bt709",
-                "-color_trc", "bt709",
-                "-colorspace", "bt709",
-                "-color_range", "tv",
-                "-chroma_sample_location", "center",
-            ]);
+        match output {
+            Output::File(filename) => {
+                if let Some(custom_ffmpeg_args) = custom_ffmpeg_args {
+                    args.extend_from_slice(custom_ffmpeg_args);
+                } else {
+                    #[rustfmt::skip]
+                    args.extend_from_slice(&[
+                        "-c:v", "libx264",
+                        "-crf", "15",
+                        "-preset", "ultrafast",
+                        "-color_primaries", "bt709",
+                        "-color_trc", "bt709",
+                        "-colorspace", "bt709",
+                        "-color_range", "tv",
+                        "-chroma_sample_location", "center",
+                    ]);
+                }
+
+                if !audio {
+                    // The audio is still written into the pipe for timing, but left out of the
+                    // output.
+                    args.push("-an");
+                }
+
+                #[rustfmt::skip]
+                args.extend_from_slice(&[
+                    "-movflags", "+faststart",
+                    "-y",
+                    filename.as_str(),
+                ]);
+            }
+            Output::Images {
+                pattern,
+                start_number: number,
+            } => {
+                // The custom arguments are meant for the video encoding.
+                args.extend_from_slice(&["-c:v", "png"]);
+
+                start_number = number.to_string();
+
+                #[rustfmt::skip]
+                args.extend_from_slice(&[
+                    "-an",
+                    "-start_number", start_number.as_str(),
+                    "-y",
+                    pattern.as_str(),
+                ]);
+            }
         }
 
-        #[rustfmt::skip]
-        args.extend_from_slice(&[
-            "-movflags", "+faststart",
-            "-y",
-            filename,
-        ]);
-
         let mut command = Command::new("ffmpeg");
         command
             .args(&args)
@@ -3,7 +3,7 @@ use std::thread::{self, JoinHandle};
 use color_eyre::eyre::{self, ensure, eyre, Context};
 use crossbeam_channel::{bounded, Receiver, Sender};
 
-use super::muxer::{Muxer, MuxerInitError, PixelFormat};
+use super::muxer::{Muxer, MuxerInitError, Output, PixelFormat};
 use super::opengl::{self, OpenGl, Uuids};
 use super::vulkan::{self, ExternalHandles, Vulkan};
 use super::SoundCaptureMode;
@@ -84,6 +84,7 @@ enum ThreadToMain {
 }
 
 impl Recorder {
+    #[allow(clippy::too_many_arguments)]
     #[instrument(name = "Recorder::init")]
     pub unsafe fn init(
         width: i32,
@@ -91,8 +92,9 @@ impl Recorder {
         fps: u64,
         slowdown: f64,
         mut capture_type: CaptureType,
-        filename: &str,
+        output: &Output,
         custom_ffmpeg_args: Option<&[&str]>,
+        audio: bool,
     ) -> eyre::Result<Recorder> {
         ensure!(
             width % 2 == 0 && height % 2 == 0,
@@ -128,8 +130,9 @@ impl Recorder {
             height as u64,
             fps,
             pixel_format,
-            filename,
+            output,
             custom_ffmpeg_args,
+            audio,
         ) {
             Ok(muxer) => muxer,
             Err(err @ MuxerInitError::FfmpegSpawn(_)) => {
@@ -2,6 +2,7 @@
 
 use std::ffi::CStr;
 use std::marker::PhantomData;
+use std::str::FromStr;
 
 use crate::hooks::engine;
 use crate::utils::*;
@@ -54,3 +55,47 @@ impl<'a> Iterator for Args<'a> {
 }
 
 impl<'a> ExactSizeIterator for Args<'a> {}
+
+/// A 3-vector console command argument.
+///
+/// Parsed from three numbers separated by spaces or commas, so it must be quoted in the console:
+/// `bxt_command "1 2 3"`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Vec3(pub glam::Vec3);
+
+impl FromStr for Vec3 {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut components = s
+            .split(|c: char| c.is_whitespace() || c == ',')
+            .filter(|component| !component.is_empty())
+            .map(f32::from_str);
+
+        let mut next = || components.next().ok_or(())?.map_err(drop);
+        let vec = glam::Vec3::new(next()?, next()?, next()?);
+
+        if components.next().is_some() {
+            return Err(());
+        }
+
+        Ok(Self(vec))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_vec3() {
+        assert_eq!("1 2 3".parse(), Ok(Vec3(glam::Vec3::new(1., 2., 3.))));
+        assert_eq!(
+            "-1.5,  0, 2e2".parse(),
+            Ok(Vec3(glam::Vec3::new(-1.5, 0., 200.)))
+        );
+        assert_eq!("1 2".parse::<Vec3>(), Err(()));
+        assert_eq!("1 2 3 4".parse::<Vec3>(), Err(()));
+        assert_eq!("1 a 3".parse::<Vec3>(), Err(()));
+    }
+}
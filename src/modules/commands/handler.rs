@@ -1,12 +1,59 @@
+use std::any::type_name;
 use std::ffi::CStr;
+use std::fmt;
 use std::str::FromStr;
 
 use super::Args;
 use crate::utils::MainThreadMarker;
 
-/// Parses a value of type `T` from the argument string.
-fn parse_arg<T: FromStr>(arg: &CStr) -> Option<T> {
-    arg.to_str().ok().and_then(|s| T::from_str(s).ok())
+/// Error of handling a console command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HandleError {
+    /// The handler accepts a different number of arguments.
+    ArgumentCount,
+    /// An argument could not be parsed into the type the handler accepts.
+    Parse {
+        /// One-based index of the argument.
+        index: usize,
+        /// The argument as entered in the console.
+        arg: String,
+        /// Name of the type the argument was parsed into.
+        type_name: &'static str,
+    },
+}
+
+impl fmt::Display for HandleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HandleError::ArgumentCount => write!(f, "wrong number of arguments"),
+            HandleError::Parse {
+                index,
+                arg,
+                type_name,
+            } => write!(
+                f,
+                "could not parse argument {index} (\"{arg}\") as {type_name}"
+            ),
+        }
+    }
+}
+
+/// Returns the type name without the module path, e.g. `PathBuf` for `std::path::PathBuf`.
+fn short_type_name<T>() -> &'static str {
+    let name = type_name::<T>();
+    name.rsplit("::").next().unwrap_or(name)
+}
+
+/// Parses a value of type `T` from the `index`-th argument string.
+fn parse_arg<T: FromStr>(index: usize, arg: &CStr) -> Result<T, HandleError> {
+    arg.to_str()
+        .ok()
+        .and_then(|s| T::from_str(s).ok())
+        .ok_or_else(|| HandleError::Parse {
+            index,
+            arg: arg.to_string_lossy().into_owned(),
+            type_name: short_type_name::<T>(),
+        })
 }
 
 /// Trait defining a console command handler.
@@ -16,7 +63,7 @@ pub trait CommandHandler {
     /// # Safety
     ///
     /// This method must only be called from a console command handler callback.
-    unsafe fn handle(self, marker: MainThreadMarker) -> bool;
+    unsafe fn handle(self, marker: MainThreadMarker) -> Result<(), HandleError>;
 }
 
 // Can't implement for Fn traits due to https://github.com/rust-lang/rust/issues/25041
@@ -28,69 +75,94 @@ pub trait CommandHandler {
 // fn(&'r _, _)" or something.
 
 impl CommandHandler for fn(MainThreadMarker) {
-    unsafe fn handle(self, marker: MainThreadMarker) -> bool {
+    unsafe fn handle(self, marker: MainThreadMarker) -> Result<(), HandleError> {
         let args = Args::new(marker).skip(1);
         if args.len() != 0 {
-            return false;
+            return Err(HandleError::ArgumentCount);
         }
 
         drop(args);
         self(marker);
 
-        true
+        Ok(())
     }
 }
 
 impl<A1: FromStr> CommandHandler for fn(MainThreadMarker, A1) {
-    unsafe fn handle(self, marker: MainThreadMarker) -> bool {
+    unsafe fn handle(self, marker: MainThreadMarker) -> Result<(), HandleError> {
         let mut args = Args::new(marker).skip(1);
         if args.len() != 1 {
-            return false;
+            return Err(HandleError::ArgumentCount);
         }
 
-        let a1 = if let Some(a1) = args.next().and_then(parse_arg) {
-            a1
-        } else {
-            return false;
-        };
+        let a1 = parse_arg(1, args.next().unwrap())?;
 
         drop(args);
         self(marker, a1);
 
-        true
+        Ok(())
     }
 }
 
 impl<A1: FromStr, A2: FromStr> CommandHandler for fn(MainThreadMarker, A1, A2) {
-    unsafe fn handle(self, marker: MainThreadMarker) -> bool {
+    unsafe fn handle(self, marker: MainThreadMarker) -> Result<(), HandleError> {
         let mut args = Args::new(marker).skip(1);
         if args.len() != 2 {
-            return false;
+            return Err(HandleError::ArgumentCount);
         }
 
-        let a1 = if let Some(a1) = args.next().and_then(parse_arg) {
-            a1
-        } else {
-            return false;
-        };
-
-        let a2 = if let Some(a2) = args.next().and_then(parse_arg) {
-            a2
-        } else {
-            return false;
-        };
+        let a1 = parse_arg(1, args.next().unwrap())?;
+        let a2 = parse_arg(2, args.next().unwrap())?;
 
         drop(args);
         self(marker, a1, a2);
 
-        true
+        Ok(())
+    }
+}
+
+impl<A1: FromStr, A2: FromStr, A3: FromStr> CommandHandler for fn(MainThreadMarker, A1, A2, A3) {
+    unsafe fn handle(self, marker: MainThreadMarker) -> Result<(), HandleError> {
+        let mut args = Args::new(marker).skip(1);
+        if args.len() != 3 {
+            return Err(HandleError::ArgumentCount);
+        }
+
+        let a1 = parse_arg(1, args.next().unwrap())?;
+        let a2 = parse_arg(2, args.next().unwrap())?;
+        let a3 = parse_arg(3, args.next().unwrap())?;
+
+        drop(args);
+        self(marker, a1, a2, a3);
+
+        Ok(())
     }
 }
 
 /// Wraps a function accepting `FromStr` arguments as a console command handler.
 ///
 /// The arguments are safely extracted and parsed into their respective types, and if the parsing
-/// fails, the help text is printed.
+/// fails, the failing argument and the help text are printed. Besides the standard types like
+/// `f32`, `usize` or `PathBuf`, a [`Vec3`](crate::modules::commands::Vec3) can be accepted as a single quoted
+/// argument.
+///
+/// For example, a command taking two angles:
+///
+/// ```ignore
+/// static BXT_SET_ANGLES: Command = Command::new(
+///     b"bxt_set_angles\0",
+///     handler!(
+///         "bxt_set_angles <pitch> <yaw>
+///
+/// Sets the view angles.",
+///         set_angles as fn(_, _, _)
+///     ),
+/// );
+///
+/// fn set_angles(marker: MainThreadMarker, pitch: f32, yaw: f32) {
+///     // ...
+/// }
+/// ```
 #[macro_export]
 macro_rules! handler {
     ($help:literal, $($fn:expr),+) => {{
@@ -103,15 +175,24 @@ macro_rules! handler {
             $crate::utils::abort_on_panic(move || {
                 let marker = $crate::utils::MainThreadMarker::new();
 
-                // Try calling all command handlers. If the argument count doesn't match they will
-                // return false.
+                // Try calling all command handlers. If the argument count doesn't match or an
+                // argument fails to parse they will return an error. Remember the first parsing
+                // error to report it since it's more helpful than the argument count.
+                let mut parse_error = None;
                 $(
-                    if $crate::modules::commands::CommandHandler::handle($fn, marker) {
-                        return;
+                    match $crate::modules::commands::CommandHandler::handle($fn, marker) {
+                        Ok(()) => return,
+                        Err($crate::modules::commands::HandleError::ArgumentCount) => (),
+                        Err(err) => {
+                            parse_error.get_or_insert(err);
+                        }
                     }
                 )+
 
-                // None of the command handlers worked, print the help text.
+                // None of the command handlers worked, print the error and the help text.
+                if let Some(err) = parse_error {
+                    $crate::hooks::engine::con_print(marker, &format!("Error: {err}.\n"));
+                }
                 $crate::hooks::engine::con_print(marker, concat!("Usage: ", $help, '\n'));
             })
         }
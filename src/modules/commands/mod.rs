@@ -8,10 +8,10 @@ use crate::hooks::engine;
 use crate::utils::*;
 
 mod args;
-pub use args::Args;
+pub use args::{Args, Vec3};
 
 mod handler;
-pub use handler::CommandHandler;
+pub use handler::{CommandHandler, HandleError};
 
 /// Console command.
 pub struct Command {
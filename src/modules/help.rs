@@ -19,7 +19,7 @@ impl Module for Help {
     }
 
     fn commands(&self) -> &'static [&'static Command] {
-        static COMMANDS: &[&Command] = &[&BXT_HELP];
+        static COMMANDS: &[&Command] = &[&BXT_HELP, &BXT_MODULES_STATUS];
         COMMANDS
     }
 
@@ -41,6 +41,58 @@ With an argument, shows help for that module, command or variable.",
     ),
 );
 
+static BXT_MODULES_STATUS: Command = Command::new(
+    b"bxt_modules_status\0",
+    handler!(
+        "bxt_modules_status
+
+Shows the status of every module along with the engine functions and variables that it requires, \
+to find out why a module is disabled.",
+        modules_status as fn(_)
+    ),
+);
+
+fn modules_status(marker: MainThreadMarker) {
+    if !Help.is_enabled(marker) {
+        return;
+    }
+
+    let mut sorted_modules = MODULES.to_vec();
+    sorted_modules.sort_unstable_by_key(|m| m.name().to_ascii_lowercase());
+
+    let mut output = format!("{:<32} Status\n", "Module");
+    for module in sorted_modules {
+        writeln!(
+            output,
+            "{:<32} {}",
+            module.name(),
+            if module.is_enabled(marker) {
+                "enabled"
+            } else {
+                "DISABLED"
+            }
+        )
+        .expect("writing to `String` should never error");
+
+        for pointer in module.required_pointers() {
+            let symbol = pointer.symbol();
+            writeln!(
+                output,
+                "  {:<30} {}",
+                String::from_utf8_lossy(&symbol[..symbol.len() - 1]),
+                if pointer.is_set(marker) {
+                    "found"
+                } else {
+                    "MISSING"
+                }
+            )
+            .expect("writing to `String` should never error");
+        }
+    }
+
+    con_print(marker, &output);
+}
+
 fn help(marker: MainThreadMarker) {
     if !Help.is_enabled(marker) {
         return;
@@ -63,6 +63,14 @@ pub trait Module: Sync {
         &[]
     }
 
+    /// Returns the engine pointers that the module requires.
+    ///
+    /// This is used to show which of them are missing when the module is disabled. Modules listing
+    /// their pointers here should check them in [`Module::is_enabled()`] using this list.
+    fn required_pointers(&self) -> &'static [&'static dyn PointerTrait] {
+        &[]
+    }
+
     /// Returns `true` if the module is enabled.
     ///
     /// If you return `false`, the module's console variables and commands will be de-registered. So
@@ -70,6 +78,18 @@ pub trait Module: Sync {
     /// the module can still work (perhaps in a limited fashion) with certain functions missing,
     /// don't include them in this check, instead check them individually before using.
     fn is_enabled(&self, marker: MainThreadMarker) -> bool;
+
+    /// Turns off the module's functionality that affects the player's movement.
+    ///
+    /// This is called for enabled modules when gameplay recording starts in the `clean` mode, so
+    /// that the recorded inputs are purely the player's. Modules implementing this should also
+    /// implement [`Module::restore_assists()`]. Currently this is only the TAS optimizer.
+    fn suspend_assists(&self, _marker: MainThreadMarker) {}
+
+    /// Restores the functionality turned off by [`Module::suspend_assists()`].
+    ///
+    /// This is called for enabled modules when the `clean` recording stops or is discarded.
+    fn restore_assists(&self, _marker: MainThreadMarker) {}
 }
 
 /// All modules.
@@ -146,7 +146,7 @@ pub unsafe fn begin_physics_frame(marker: MainThreadMarker) {
             .get_opt(marker)
             .map(|frame_time| *frame_time);
         let client_state = engine::cls.get_opt(marker).map(|cls| (*cls).state);
-        let is_paused = engine::sv.get_opt(marker).map(|sv| *sv.offset(4).cast());
+        let is_paused = engine::sv_paused(marker);
 
         // TODO: command_buffer
         if let Err(err) = tas_log.begin_physics_frame(
@@ -16,7 +16,7 @@ use super::Module;
 use crate::ffi::edict;
 use crate::handler;
 use crate::hooks::bxt;
-use crate::hooks::engine::{self, con_print};
+use crate::hooks::engine::{self, con_print, get_cvar_f32};
 use crate::modules::commands::{self, Command};
 use crate::utils::*;
 
@@ -86,10 +86,24 @@ impl Module for TasOptimizer {
             && engine::svs.is_set(marker)
             && engine::host_frametime.is_set(marker)
     }
+
+    fn suspend_assists(&self, marker: MainThreadMarker) {
+        // The optimizer plays the script back on its own while optimizing.
+        OPTIMIZE_SUSPENDED.set(marker, OPTIMIZE.get(marker));
+        OPTIMIZE.set(marker, false);
+    }
+
+    fn restore_assists(&self, marker: MainThreadMarker) {
+        if OPTIMIZE_SUSPENDED.get(marker) {
+            set_optimize(marker, true);
+        }
+    }
 }
 
 static OPTIMIZER: MainThreadRefCell<Option<Optimizer>> = MainThreadRefCell::new(None);
 static OPTIMIZE: MainThreadCell<bool> = MainThreadCell::new(false);
+/// Whether the optimization was stopped by [`Module::suspend_assists()`] and should be resumed.
+static OPTIMIZE_SUSPENDED: MainThreadCell<bool> = MainThreadCell::new(false);
 static OBJECTIVE: MainThreadRefCell<Objective> = MainThreadRefCell::new(Objective::Console {
     variable: Variable::PosX,
     direction: Direction::Maximize,
@@ -270,23 +284,6 @@ arguments) provided in Bunnymod XT, which sets the script name and frame number
     ),
 );
 
-unsafe fn get_cvar_f32(marker: MainThreadMarker, name: &str) -> Option<f32> {
-    let mut ptr = *engine::cvar_vars.get(marker);
-    while !ptr.is_null() {
-        match std::ffi::CStr::from_ptr((*ptr).name).to_str() {
-            Ok(x) if x == name => {
-                return Some((*ptr).value);
-            }
-            _ => (),
-        }
-
-        ptr = (*ptr).next;
-    }
-
-    warn!("couldn't find cvar {}", name);
-    None
-}
-
 fn next_generation(marker: MainThreadMarker) -> u16 {
     static GENERATION: MainThreadCell<u16> = MainThreadCell::new(0);
     let generation = GENERATION.get(marker);
@@ -368,7 +365,7 @@ fn optim_init(marker: MainThreadMarker, path: PathBuf, first_frame: usize) {
         next_generation(marker),
     ));
 
-    OPTIMIZE.set(marker, false);
+    set_optimize(marker, false);
 
     if let Err(err) = remote::start_server() {
         con_print(
@@ -390,7 +387,7 @@ Stops and disables the optimizer.",
 
 fn optim_disable(marker: MainThreadMarker) {
     *OPTIMIZER.borrow_mut(marker) = None;
-    OPTIMIZE.set(marker, false);
+    set_optimize(marker, false);
 }
 
 static BXT_TAS_OPTIM_RESET: Command = Command::new(
@@ -409,7 +406,7 @@ bxt_tas_optim_multiple_games, to start from scratch without having to replay the
 fn optim_reset(marker: MainThreadMarker) {
     if let Some(optimizer) = &mut *OPTIMIZER.borrow_mut(marker) {
         optimizer.reset(next_generation(marker));
-        OPTIMIZE.set(marker, false);
+        set_optimize(marker, false);
     } else {
         con_print(marker, "The optimizer is not initialized.\n");
     }
@@ -593,7 +590,7 @@ fn optim_start(marker: MainThreadMarker) {
         };
     }
 
-    OPTIMIZE.set(marker, true);
+    set_optimize(marker, true);
 
     OPTIM_STATS_LAST_PRINTED_AT.set(marker, Some(Instant::now()));
     OPTIM_STATS_ITERATIONS.set(marker, 0);
@@ -611,7 +608,13 @@ Stops the optimization.",
 );
 
 fn optim_stop(marker: MainThreadMarker) {
-    OPTIMIZE.set(marker, false);
+    set_optimize(marker, false);
+}
+
+/// Starts or stops the optimization, overriding an earlier suspension.
+fn set_optimize(marker: MainThreadMarker, value: bool) {
+    OPTIMIZE.set(marker, value);
+    OPTIMIZE_SUSPENDED.set(marker, false);
 }
 
 static BXT_TAS_OPTIM_SAVE: Command = Command::new(
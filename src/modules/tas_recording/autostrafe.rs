@@ -0,0 +1,152 @@
+//! Detection of strafing in the recorded commands.
+
+use std::f32::consts::{FRAC_PI_2, PI, TAU};
+
+use hltas::types::{StrafeDir, StrafeSettings, StrafeType};
+
+/// Maximum difference between the recorded and the optimal strafing angle, in degrees.
+const TOLERANCE: f32 = 0.5;
+
+/// Movement parameters relevant for air strafing.
+#[derive(Debug, Clone, Copy)]
+pub struct Parameters {
+    pub frame_time: f32,
+    pub max_speed: f32,
+    pub air_accelerate: f32,
+    pub ent_friction: f32,
+}
+
+/// Returns the strafe settings which reproduce the command, if it looks like air strafing.
+///
+/// `velocity` is the horizontal player velocity before the command, `yaw` is the command view
+/// yaw in degrees. The command is considered strafing if the angle between the velocity and the
+/// resulting wish direction is within [`TOLERANCE`] of the maximum acceleration angle.
+pub fn detect(
+    parameters: Parameters,
+    velocity: [f32; 2],
+    yaw: f32,
+    forwardmove: f32,
+    sidemove: f32,
+) -> Option<StrafeSettings> {
+    let speed = velocity[0].hypot(velocity[1]);
+    if speed == 0. {
+        return None;
+    }
+
+    let wish_speed = forwardmove.hypot(sidemove).min(parameters.max_speed);
+    if wish_speed == 0. {
+        return None;
+    }
+
+    let theta = max_accel_theta(parameters, speed, wish_speed);
+    if theta == 0. {
+        // Can't gain any speed, so there's nothing to tell strafing apart from just moving.
+        return None;
+    }
+
+    let vel_yaw = velocity[1].atan2(velocity[0]);
+    // Positive sidemove points to the right.
+    let wish_yaw = yaw.to_radians() + (-sidemove).atan2(forwardmove);
+    let angle = normalize_rad(wish_yaw - vel_yaw);
+
+    if (angle.abs() - theta).abs() > TOLERANCE.to_radians() {
+        return None;
+    }
+
+    let dir = if angle > 0. {
+        StrafeDir::Left
+    } else {
+        StrafeDir::Right
+    };
+
+    Some(StrafeSettings {
+        type_: StrafeType::MaxAccel,
+        dir,
+    })
+}
+
+/// Returns the angle between the velocity and the wish direction giving the maximum acceleration
+/// in the air.
+fn max_accel_theta(parameters: Parameters, speed: f32, wish_speed: f32) -> f32 {
+    let accel_speed =
+        parameters.air_accelerate * wish_speed * parameters.ent_friction * parameters.frame_time;
+    if accel_speed <= 0. {
+        return PI;
+    }
+
+    let tmp = wish_speed.min(30.) - accel_speed;
+    if tmp <= 0. {
+        return FRAC_PI_2;
+    }
+
+    if tmp < speed {
+        return (tmp / speed).acos();
+    }
+
+    0.
+}
+
+/// Normalizes the angle into `[-π; π)`.
+fn normalize_rad(angle: f32) -> f32 {
+    (angle + PI).rem_euclid(TAU) - PI
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PARAMETERS: Parameters = Parameters {
+        frame_time: 0.01,
+        max_speed: 320.,
+        air_accelerate: 10.,
+        ent_friction: 1.,
+    };
+
+    /// Returns the view yaw which strafes optimally with the given velocity and sidemove.
+    fn optimal_yaw(velocity: [f32; 2], sidemove: f32, left: bool) -> f32 {
+        let speed = velocity[0].hypot(velocity[1]);
+        let theta = max_accel_theta(PARAMETERS, speed, sidemove.abs());
+        let wish_yaw = velocity[1].atan2(velocity[0]) + if left { theta } else { -theta };
+        (wish_yaw - (-sidemove).atan2(0.)).to_degrees()
+    }
+
+    #[test]
+    fn detect_left() {
+        let velocity = [300., 100.];
+        let yaw = optimal_yaw(velocity, -400., true);
+        assert_eq!(
+            detect(PARAMETERS, velocity, yaw, 0., -400.),
+            Some(StrafeSettings {
+                type_: StrafeType::MaxAccel,
+                dir: StrafeDir::Left,
+            })
+        );
+    }
+
+    #[test]
+    fn detect_right() {
+        let velocity = [-200., 350.];
+        let yaw = optimal_yaw(velocity, 400., false);
+        assert_eq!(
+            detect(PARAMETERS, velocity, yaw, 0., 400.),
+            Some(StrafeSettings {
+                type_: StrafeType::MaxAccel,
+                dir: StrafeDir::Right,
+            })
+        );
+    }
+
+    #[test]
+    fn dont_detect_off_angle() {
+        let velocity = [300., 100.];
+        let yaw = optimal_yaw(velocity, -400., true);
+        assert_eq!(detect(PARAMETERS, velocity, yaw + 5., 0., -400.), None);
+        assert_eq!(detect(PARAMETERS, velocity, yaw - 5., 0., -400.), None);
+    }
+
+    #[test]
+    fn dont_detect_without_movement() {
+        assert_eq!(detect(PARAMETERS, [0., 0.], 0., 0., -400.), None);
+        assert_eq!(detect(PARAMETERS, [300., 0.], 0., 0., 0.), None);
+    }
+}
@@ -0,0 +1,191 @@
+//! Compact binary recording format.
+//!
+//! The file starts with a header followed by one record per physics frame, until the end of the
+//! file. All values are little-endian.
+//!
+//! The header:
+//!
+//! | Offset | Size | Contents              |
+//! |--------|------|-----------------------|
+//! | 0      | 4    | Magic bytes, `BXTR`.  |
+//! | 4      | 4    | Version, `u32`, `2`.  |
+//!
+//! A record:
+//!
+//! | Offset | Size | Contents                            |
+//! |--------|------|-------------------------------------|
+//! | 0      | 8    | Frame time, `f64`.                  |
+//! | 8      | 12   | View angles, 3 × `f32`.             |
+//! | 20     | 4    | Forward move, `f32`.                |
+//! | 24     | 4    | Side move, `f32`.                   |
+//! | 28     | 4    | Up move, `f32`.                     |
+//! | 32     | 2    | Buttons, `u16`.                     |
+//! | 34     | 1    | Command duration in ms, `u8`.       |
+//! | 35     | 4    | `cl_movespeedkey`, `f32`.           |
+//! | 39     | 8    | Frame time remainder, `f64`.        |
+
+use std::io::{self, Read, Write};
+
+/// Magic bytes at the start of the file.
+const MAGIC: &[u8; 4] = b"BXTR";
+
+/// Version of the format.
+const VERSION: u32 = 2;
+
+/// Size of a record in bytes.
+const RECORD_SIZE: usize = 47;
+
+/// Command data of a physics frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Record {
+    pub frame_time: f64,
+    pub viewangles: [f32; 3],
+    pub forwardmove: f32,
+    pub sidemove: f32,
+    pub upmove: f32,
+    pub buttons: u16,
+    pub msec: u8,
+    pub movespeedkey: f32,
+    pub frametime_remainder: f64,
+}
+
+/// Writes the file header.
+pub fn write_header(mut writer: impl Write) -> io::Result<()> {
+    writer.write_all(MAGIC)?;
+    writer.write_all(&VERSION.to_le_bytes())
+}
+
+impl Record {
+    /// Writes the record.
+    pub fn write(&self, mut writer: impl Write) -> io::Result<()> {
+        let mut buf = Vec::with_capacity(RECORD_SIZE);
+        buf.extend_from_slice(&self.frame_time.to_le_bytes());
+        for angle in self.viewangles {
+            buf.extend_from_slice(&angle.to_le_bytes());
+        }
+        buf.extend_from_slice(&self.forwardmove.to_le_bytes());
+        buf.extend_from_slice(&self.sidemove.to_le_bytes());
+        buf.extend_from_slice(&self.upmove.to_le_bytes());
+        buf.extend_from_slice(&self.buttons.to_le_bytes());
+        buf.push(self.msec);
+        buf.extend_from_slice(&self.movespeedkey.to_le_bytes());
+        buf.extend_from_slice(&self.frametime_remainder.to_le_bytes());
+        writer.write_all(&buf)
+    }
+
+    /// Parses a record from its bytes.
+    fn from_bytes(buf: &[u8; RECORD_SIZE]) -> Self {
+        let f32_at =
+            |offset: usize| f32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap());
+
+        Self {
+            frame_time: f64::from_le_bytes(buf[0..8].try_into().unwrap()),
+            viewangles: [f32_at(8), f32_at(12), f32_at(16)],
+            forwardmove: f32_at(20),
+            sidemove: f32_at(24),
+            upmove: f32_at(28),
+            buttons: u16::from_le_bytes(buf[32..34].try_into().unwrap()),
+            msec: buf[34],
+            movespeedkey: f32_at(35),
+            frametime_remainder: f64::from_le_bytes(buf[39..47].try_into().unwrap()),
+        }
+    }
+}
+
+/// Reads all records of a file, checking the header.
+pub fn read_records(mut reader: impl Read) -> io::Result<Vec<Record>> {
+    let mut header = [0; 8];
+    reader.read_exact(&mut header)?;
+    if header[..4] != MAGIC[..] {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "not a binary recording",
+        ));
+    }
+    let version = u32::from_le_bytes(header[4..].try_into().unwrap());
+    if version != VERSION {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("unsupported version {}", version),
+        ));
+    }
+
+    let mut records = Vec::new();
+    let mut buf = [0; RECORD_SIZE];
+    loop {
+        let mut len = 0;
+        while len < RECORD_SIZE {
+            match reader.read(&mut buf[len..]) {
+                Ok(0) => break,
+                Ok(read) => len += read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => (),
+                Err(err) => return Err(err),
+            }
+        }
+
+        match len {
+            0 => return Ok(records),
+            RECORD_SIZE => records.push(Record::from_bytes(&buf)),
+            _ => {
+                return Err(io::Error::new(
+                    io::ErrorKind::UnexpectedEof,
+                    format!("truncated record {}", records.len() + 1),
+                ))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RECORD: Record = Record {
+        frame_time: 0.001,
+        viewangles: [-10.5, 90., 0.],
+        forwardmove: 400.,
+        sidemove: -200.,
+        upmove: 0.,
+        buttons: 2 | 8,
+        msec: 1,
+        movespeedkey: 0.3,
+        frametime_remainder: 0.25,
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut file = Vec::new();
+        write_header(&mut file).unwrap();
+        RECORD.write(&mut file).unwrap();
+        let second = Record {
+            buttons: 0,
+            ..RECORD
+        };
+        second.write(&mut file).unwrap();
+
+        assert_eq!(file.len(), 8 + 2 * RECORD_SIZE);
+        assert_eq!(read_records(&file[..]).unwrap(), [RECORD, second]);
+    }
+
+    #[test]
+    fn empty_recording() {
+        let mut file = Vec::new();
+        write_header(&mut file).unwrap();
+        assert_eq!(read_records(&file[..]).unwrap(), []);
+    }
+
+    #[test]
+    fn invalid_files() {
+        assert!(read_records(&b"version 1\n"[..]).is_err());
+        assert!(read_records(&b"BXTR\x01\0\0\0"[..]).is_err());
+
+        let mut file = Vec::new();
+        write_header(&mut file).unwrap();
+        RECORD.write(&mut file).unwrap();
+        file.pop();
+        assert_eq!(
+            read_records(&file[..]).unwrap_err().kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+}
@@ -0,0 +1,1331 @@
+//! Console commands controlling the recording.
+
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::io::{self, Write as _};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use hltas::types::Line;
+use hltas::HLTAS;
+
+use super::frames::{mark_zero_ms_frame_bulk, push_remainder_command, uncount_frame_time};
+use super::sink::PlayerPosition;
+use super::transforms::{
+    drop_incomplete_frame_bulks, remove_frame_bulks, rotate_yaws, transform_lines, trim_idle_start,
+};
+use super::{
+    binary, cvar_string, discard, format_frametime, part_filename, play_next_batch_demo,
+    recording_path, request_stop, restore_assists, serialize_script, start, stop,
+    take_stopped_recorder, usercmd_to_frame_bulk, with_appended_extension, write_script, Batch,
+    Keys, Mode, Recorder, SelfTest, State, TasRecording, BATCH, LAST_ERROR, SELFTEST, STATE,
+};
+use crate::ffi::usercmd::usercmd_s;
+use crate::handler;
+use crate::hooks::engine::{self, con_print};
+use crate::modules::commands::Command;
+use crate::modules::{Module, MODULES};
+use crate::utils::*;
+
+/// Console commands of the module.
+pub(super) static COMMANDS: &[&Command] = &[
+    &BXT_TAS_RECORDING_START,
+    &BXT_TAS_RECORDING_STOP,
+    &BXT_TAS_RECORDING_DISCARD,
+    &BXT_TAS_RECORDING_PAUSE,
+    &BXT_TAS_RECORDING_RESUME,
+    &BXT_TAS_RECORDING_VALIDATE,
+    &BXT_TAS_RECORDING_DUMP_STDOUT,
+    &BXT_TAS_RECORDING_MODE,
+    &BXT_TAS_RECORDING_STATUS,
+    &BXT_TAS_RECORDING_FROM_DEMOS,
+    &BXT_TAS_RECORDING_UNDO,
+    &BXT_TAS_RECORDING_TRIM_START,
+    &BXT_TAS_RECORDING_MARK,
+    &BXT_TAS_RECORDING_STOP_AT,
+    &BXT_TAS_RECORDING_ROTATE,
+    &BXT_TAS_RECORDING_SELFTEST,
+    &BXT_TAS_RECORDING_CONVERT,
+    &BXT_TAS_RECORDING_LAST_ERROR,
+];
+
+static BXT_TAS_RECORDING_START: Command = Command::new(
+    b"bxt_tas_recording_start\0",
+    handler!(
+        "bxt_tas_recording_start <filename.hltas> [append|overwrite] [clean]
+
+Starts recording gameplay into a HLTAS script.
+
+If the script already exists, the recording doesn't start unless `append` or `overwrite` is given. \
+With `append`, the recording is appended to the end of the script. With `overwrite`, the script is \
+replaced when the recording stops.
+
+The file name can contain a `%d` or a zero-padded `%03d` pattern, which is replaced with the lowest \
+number starting from 1 that gives a file which doesn't exist yet, like `run_%03d.hltas` turning \
+into `run_001.hltas`, then `run_002.hltas` and so on.
+
+With `clean`, the movement assists of other modules are turned off for the duration of the \
+recording so that the recorded inputs are purely the player's, and restored when the recording \
+stops. Currently this pauses the TAS optimizer. On top of that, the movement assists of Bunnymod \
+XT are turned off too: autojump (`bxt_autojump`) and the removal of the bunnyhop cap \
+(`bxt_bhopcap`). `clean` can be combined with `append` or `overwrite`.",
+        tas_recording_start as fn(_, _),
+        tas_recording_start_append as fn(_, _, _),
+        tas_recording_start_overwrite as fn(_, _, _),
+        tas_recording_start_clean as fn(_, _, _),
+        tas_recording_start_append_clean as fn(_, _, _, _),
+        tas_recording_start_overwrite_clean as fn(_, _, _, _)
+    ),
+);
+
+static BXT_TAS_RECORDING_STOP: Command = Command::new(
+    b"bxt_tas_recording_stop\0",
+    handler!(
+        "bxt_tas_recording_stop
+
+Stops gameplay recording and prints a histogram of the frame times of the recording, which shows \
+whether the frame rate stayed stable.",
+        tas_recording_stop as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_DISCARD: Command = Command::new(
+    b"bxt_tas_recording_discard\0",
+    handler!(
+        "bxt_tas_recording_discard
+
+Stops gameplay recording without writing the script, throwing away everything recorded so far.",
+        tas_recording_discard as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_PAUSE: Command = Command::new(
+    b"bxt_tas_recording_pause\0",
+    handler!(
+        "bxt_tas_recording_pause
+
+Pauses gameplay recording without stopping it. Use `bxt_tas_recording_resume` to continue.",
+        tas_recording_pause as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_RESUME: Command = Command::new(
+    b"bxt_tas_recording_resume\0",
+    handler!(
+        "bxt_tas_recording_resume
+
+Resumes paused gameplay recording.",
+        tas_recording_resume as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_VALIDATE: Command = Command::new(
+    b"bxt_tas_recording_validate\0",
+    handler!(
+        "bxt_tas_recording_validate
+
+Checks that the script recorded so far can be written out and parsed back, without stopping the \
+recording.",
+        tas_recording_validate as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_DUMP_STDOUT: Command = Command::new(
+    b"bxt_tas_recording_dump_stdout\0",
+    handler!(
+        "bxt_tas_recording_dump_stdout
+
+Prints the script recorded so far to the standard output of the game, without stopping the \
+recording. Useful for previewing the recording from an external tool.",
+        tas_recording_dump_stdout as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_CONVERT: Command = Command::new(
+    b"bxt_tas_recording_convert\0",
+    handler!(
+        "bxt_tas_recording_convert <input.bin> <output.hltas>
+
+Converts a recording made with `_bxt_tas_recording_format bin` into a script. The records keep the \
+command duration, `cl_movespeedkey` and the frame time remainder, so 0 ms frames, slow movement \
+and the remainders come out like in a regular recording.",
+        tas_recording_convert as fn(_, _, _)
+    ),
+);
+
+static BXT_TAS_RECORDING_LAST_ERROR: Command = Command::new(
+    b"bxt_tas_recording_last_error\0",
+    handler!(
+        "bxt_tas_recording_last_error
+
+Prints the last error of the recording, like failing to write the script, or `none`. The error is \
+cleared when a recording stops successfully.",
+        tas_recording_last_error as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_MODE: Command = Command::new(
+    b"bxt_tas_recording_mode\0",
+    handler!(
+        "bxt_tas_recording_mode <raw|strafe>
+
+Changes how the following frames of the current recording encode the movement: `raw` always \
+records the yaw, while `strafe` records air strafing as autostrafing. Recordings start in the mode \
+set by `bxt_tas_recording_autostrafe`.",
+        tas_recording_mode as fn(_, _)
+    ),
+);
+
+static BXT_TAS_RECORDING_STATUS: Command = Command::new(
+    b"bxt_tas_recording_status\0",
+    handler!(
+        "bxt_tas_recording_status
+
+Prints whether gameplay is being recorded, and if so, the output file name and the recording \
+progress.",
+        tas_recording_status as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_FROM_DEMOS: Command = Command::new(
+    b"bxt_tas_recording_from_demos\0",
+    handler!(
+        "bxt_tas_recording_from_demos <directory>
+
+Plays back every demo in the directory, relative to the game directory, and records each of them \
+into a HLTAS script with the same name next to the demo.",
+        tas_recording_from_demos as fn(_, _)
+    ),
+);
+
+static BXT_TAS_RECORDING_UNDO: Command = Command::new(
+    b"bxt_tas_recording_undo\0",
+    handler!(
+        "bxt_tas_recording_undo <n>
+
+Removes the last `n` recorded frame bulks without stopping the recording, keeping the other lines \
+such as comments. Useful together with loading a save to re-attempt a part of the recording.",
+        tas_recording_undo as fn(_, _)
+    ),
+);
+
+static BXT_TAS_RECORDING_TRIM_START: Command = Command::new(
+    b"bxt_tas_recording_trim_start\0",
+    handler!(
+        "bxt_tas_recording_trim_start
+
+Removes the frame bulks at the start of the recording where nothing is pressed and no console \
+commands run, up to the first frame bulk that does something. Useful after starting the recording \
+a bit too early.",
+        tas_recording_trim_start as fn(_)
+    ),
+);
+
+static BXT_TAS_RECORDING_MARK: Command = Command::new(
+    b"bxt_tas_recording_mark\0",
+    handler!(
+        "bxt_tas_recording_mark <label>
+
+Adds a comment with the label and the current frame into the recording, to find the spot later. \
+The frame is the number of frames recorded since the recording started, not the engine's own frame \
+counter.",
+        tas_recording_mark as fn(_, _)
+    ),
+);
+
+static BXT_TAS_RECORDING_STOP_AT: Command = Command::new(
+    b"bxt_tas_recording_stop_at\0",
+    handler!(
+        "bxt_tas_recording_stop_at <frame>
+
+Stops the recording once it reaches the given frame, the same way as `bxt_tas_recording_stop`. \
+The frame is the number of frames recorded since the recording started, as shown by \
+`bxt_tas_recording_mark`. If the frame has already passed, the recording stops right away.",
+        tas_recording_stop_at as fn(_, _)
+    ),
+);
+
+static BXT_TAS_RECORDING_ROTATE: Command = Command::new(
+    b"bxt_tas_recording_rotate\0",
+    handler!(
+        "bxt_tas_recording_rotate <degrees>
+
+Rotates the run recorded so far by adding `degrees` to every recorded yaw, without stopping the \
+recording. Strafing frame bulks find their yaw on their own and are left as is.",
+        tas_recording_rotate as fn(_, _)
+    ),
+);
+
+static BXT_TAS_RECORDING_SELFTEST: Command = Command::new(
+    b"bxt_tas_recording_selftest\0",
+    handler!(
+        "bxt_tas_recording_selftest <filename.hltas>
+
+Replays a recorded script with Bunnymod XT's `bxt_tas_loadscript` and compares the player origin \
+and view angles on every tick against the ones logged during the recording, reporting the first \
+tick where the replay goes off-course. The script must have been recorded with \
+`_bxt_tas_recording_log_origins 1`, so that the `.origins` file exists next to it.",
+        tas_recording_selftest as fn(_, _)
+    ),
+);
+
+/// The `overwrite` argument of `bxt_tas_recording_start`.
+struct Overwrite;
+
+impl FromStr for Overwrite {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "overwrite" {
+            Ok(Self)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// The `append` argument of `bxt_tas_recording_start`.
+struct Append;
+
+impl FromStr for Append {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "append" {
+            Ok(Self)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// The `clean` argument of `bxt_tas_recording_start`.
+struct Clean;
+
+impl FromStr for Clean {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "clean" {
+            Ok(Self)
+        } else {
+            Err(())
+        }
+    }
+}
+
+/// Console variables of the movement assists along with their values which turn the assist off.
+///
+/// These belong to Bunnymod XT, which bxt-rs often runs alongside of, so they are turned off by the
+/// `clean` mode in addition to suspending the assists of the modules.
+const CLEAN_CVARS: &[(&str, &str)] = &[("bxt_autojump", "0"), ("bxt_bhopcap", "1")];
+
+fn tas_recording_start(marker: MainThreadMarker, filename: PathBuf) {
+    start_recording(marker, filename, false);
+}
+
+/// Starts recording into the script, like `bxt_tas_recording_start`.
+///
+/// Doesn't start if the script already exists, unless `force` is set, in which case the script is
+/// overwritten. The outcome is printed to the console.
+pub fn start_recording(marker: MainThreadMarker, filename: PathBuf, force: bool) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    if matches!(*STATE.borrow(marker), State::Recording(_)) {
+        con_print(marker, "Already recording\n");
+        return;
+    }
+
+    let path = new_recording_path(marker, &filename);
+    if !force && path.exists() {
+        con_print(
+            marker,
+            &format!(
+                "Warning: {} already exists, not starting the recording. Use \
+                 `bxt_tas_recording_start {} overwrite` to overwrite it or `append` to append to \
+                 it.\n",
+                path.display(),
+                filename.display(),
+            ),
+        );
+        return;
+    }
+
+    start(marker, path, HLTAS::default());
+}
+
+fn tas_recording_start_overwrite(
+    marker: MainThreadMarker,
+    filename: PathBuf,
+    _overwrite: Overwrite,
+) {
+    start_recording(marker, filename, true);
+}
+
+fn tas_recording_start_clean(marker: MainThreadMarker, filename: PathBuf, _clean: Clean) {
+    start_clean(marker, |marker| start_recording(marker, filename, false));
+}
+
+fn tas_recording_start_append_clean(
+    marker: MainThreadMarker,
+    filename: PathBuf,
+    append: Append,
+    _clean: Clean,
+) {
+    start_clean(marker, |marker| {
+        tas_recording_start_append(marker, filename, append)
+    });
+}
+
+fn tas_recording_start_overwrite_clean(
+    marker: MainThreadMarker,
+    filename: PathBuf,
+    overwrite: Overwrite,
+    _clean: Clean,
+) {
+    start_clean(marker, |marker| {
+        tas_recording_start_overwrite(marker, filename, overwrite)
+    });
+}
+
+/// Starts the recording with `begin`, then turns off the movement assists if it started.
+fn start_clean(marker: MainThreadMarker, begin: impl FnOnce(MainThreadMarker)) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    if !engine::Cbuf_InsertText.is_set(marker) {
+        con_print(marker, "Error: cannot run console commands\n");
+        return;
+    }
+
+    if matches!(*STATE.borrow(marker), State::Recording(_)) {
+        con_print(marker, "Already recording\n");
+        return;
+    }
+
+    begin(marker);
+
+    if let State::Recording(recorder) = &mut *STATE.borrow_mut(marker) {
+        for module in MODULES {
+            if module.is_enabled(marker) {
+                module.suspend_assists(marker);
+            }
+        }
+        recorder.assists_suspended = true;
+
+        // SAFETY: we're in a console command callback, it's safe to call engine functions.
+        let (suspended, commands) = suspend_commands(|name| unsafe { cvar_string(marker, name) });
+        if !commands.is_empty() {
+            engine::prepend_command(marker, &commands);
+        }
+        recorder.suspended_cvars = suspended;
+    }
+}
+
+/// Returns the values of the existing movement assist variables and the commands turning them off.
+fn suspend_commands(value: impl Fn(&str) -> Option<String>) -> (Vec<(String, String)>, String) {
+    let mut suspended = Vec::new();
+    let mut commands = String::new();
+    for &(name, clean_value) in CLEAN_CVARS {
+        if let Some(value) = value(name) {
+            writeln!(commands, "{} \"{}\"", name, clean_value)
+                .expect("writing to `String` should never error");
+            suspended.push((name.to_owned(), value));
+        }
+    }
+
+    (suspended, commands)
+}
+
+fn tas_recording_start_append(marker: MainThreadMarker, filename: PathBuf, _append: Append) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    if matches!(*STATE.borrow(marker), State::Recording(_)) {
+        con_print(marker, "Already recording\n");
+        return;
+    }
+
+    let filename = new_recording_path(marker, &filename);
+    let hltas = if filename.exists() {
+        let script = match fs::read_to_string(&filename) {
+            Ok(x) => x,
+            Err(err) => {
+                con_print(marker, &format!("Error reading the script: {}\n", err));
+                return;
+            }
+        };
+
+        match HLTAS::from_str(&script) {
+            Ok(x) => x,
+            Err(err) => {
+                con_print(marker, &format!("Error parsing the script: {}\n", err));
+                return;
+            }
+        }
+    } else {
+        HLTAS::default()
+    };
+
+    start(marker, filename, hltas);
+}
+
+/// Returns the path of the file to start recording into, with the index template expanded.
+fn new_recording_path(marker: MainThreadMarker, filename: &Path) -> PathBuf {
+    expand_recording_path(recording_path(marker, filename), Path::exists)
+}
+
+/// Expands the index template in the path, leaving paths without one as is.
+fn expand_recording_path(path: PathBuf, exists: impl Fn(&Path) -> bool) -> PathBuf {
+    expand_index_template(&path, exists).unwrap_or(path)
+}
+
+/// Replaces a `%d` or `%0Nd` pattern in the path with the lowest index starting from 1 for which
+/// `exists` returns `false`.
+///
+/// Returns `None` if the path has no pattern.
+fn expand_index_template(path: &Path, exists: impl Fn(&Path) -> bool) -> Option<PathBuf> {
+    let path = path.to_str()?;
+
+    let start = path.find('%')?;
+    let rest = &path[start + 1..];
+    let width_len = rest.find(|c: char| !c.is_ascii_digit())?;
+    if !rest[width_len..].starts_with('d') {
+        return None;
+    }
+    let width = match &rest[..width_len] {
+        "" => 0,
+        width if width.starts_with('0') => width.parse().ok()?,
+        _ => return None,
+    };
+
+    let prefix = &path[..start];
+    let suffix = &rest[width_len + 1..];
+    (1..)
+        .map(|index: u32| PathBuf::from(format!("{}{:0width$}{}", prefix, index, suffix)))
+        .find(|candidate| !exists(candidate))
+}
+
+fn tas_recording_stop(marker: MainThreadMarker) {
+    stop_recording(marker);
+}
+
+/// Stops the recording at the end of the current frame, like `bxt_tas_recording_stop`.
+pub fn stop_recording(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    if !request_stop(&mut state) {
+        con_print(marker, "No recording in progress\n");
+        return;
+    }
+
+    // The recording is finished in on_sv_frame_end() rather than here so that the state is never
+    // torn down in the middle of a frame. Without a running server, like in the main menu or after
+    // a disconnect, there are no frames to wait for.
+    // SAFETY: we're in a console command callback, so the server data isn't being modified.
+    if unsafe { engine::sv_active(marker) } == Some(true) {
+        con_print(marker, "Recording will stop at the end of the frame\n");
+    } else if let Some(recorder) = take_stopped_recorder(&mut state) {
+        stop(marker, recorder);
+    }
+}
+
+fn tas_recording_discard(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    if let State::Recording(recorder) = mem::replace(&mut *state, State::Idle) {
+        restore_assists(
+            marker,
+            recorder.assists_suspended,
+            &recorder.suspended_cvars,
+        );
+        discard(recorder);
+        con_print(marker, "Recording discarded\n");
+    } else {
+        con_print(marker, "No recording in progress\n");
+    }
+}
+
+fn tas_recording_pause(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    if recorder.paused {
+        con_print(marker, "Recording is already paused\n");
+        return;
+    }
+
+    recorder.paused = true;
+
+    // Don't carry anything over into the resumed recording.
+    recorder.pending_frame_times.clear();
+    recorder.pending_msecs.clear();
+    recorder.pending_remainders.clear();
+    recorder.pending_bound_commands.clear();
+    recorder.pending_console_commands.clear();
+    recorder.pause = None;
+
+    con_print(marker, "Recording paused\n");
+}
+
+fn tas_recording_resume(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    if !recorder.paused {
+        con_print(marker, "Recording is not paused\n");
+        return;
+    }
+
+    // The shared seed and the non-shared RNG state of the last recorded frame are kept, so the
+    // first resumed command records their changes.
+    recorder.paused = false;
+
+    con_print(marker, "Recording resumed\n");
+}
+
+fn tas_recording_undo(marker: MainThreadMarker, count: usize) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    let removed = undo_frame_bulks(recorder, count);
+    con_print(marker, &format!("Removed {} frame bulks\n", removed));
+}
+
+/// Removes the last `count` frame bulks from the recording, returning how many were removed.
+fn undo_frame_bulks(recorder: &mut Recorder, count: usize) -> usize {
+    // Console commands run outside of physics frames, so every removed frame bulk already has its
+    // frame time and the pending frame data is empty. It stays empty, so that the next frame is
+    // filled in as usual.
+    let removed = remove_frame_bulks(&mut recorder.hltas.lines, count);
+    for frame_bulk in &removed {
+        let frame_time = if frame_bulk.frame_time == "0" {
+            recorder
+                .hltas
+                .properties
+                .frametime_0ms
+                .as_deref()
+                .unwrap_or("0")
+        } else {
+            &frame_bulk.frame_time
+        };
+        uncount_frame_time(&mut recorder.frame_time_counts, frame_time);
+    }
+
+    let removed = removed.len();
+    recorder.frame_count = recorder.frame_count.saturating_sub(removed as u64);
+
+    // The view angles can be anywhere when the recording continues.
+    recorder.last_viewangles = None;
+
+    removed
+}
+
+fn tas_recording_trim_start(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    // Only the frame bulks with frame times are trimmed, so the pending frame data stays aligned.
+    let trimmed = trim_idle_start(&mut recorder.hltas.lines);
+    recorder.frame_count = recorder.frame_count.saturating_sub(trimmed);
+
+    con_print(marker, &format!("Trimmed {} frames\n", trimmed));
+}
+
+fn tas_recording_rotate(marker: MainThreadMarker, degrees: f32) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    let rotated = rotate_yaws(&mut recorder.hltas.lines, degrees);
+
+    // The rest of the recording continues from the actual view angles.
+    recorder.last_viewangles = None;
+
+    con_print(marker, &format!("Rotated {} frame bulks\n", rotated));
+}
+
+fn tas_recording_selftest(marker: MainThreadMarker, filename: PathBuf) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    if !engine::Cbuf_InsertText.is_set(marker) {
+        con_print(marker, "Error: cannot run console commands\n");
+        return;
+    }
+
+    let path = recording_path(marker, &filename);
+    let origins_path = with_appended_extension(&path, "origins");
+    let origins = match fs::read_to_string(&origins_path) {
+        Ok(x) => x,
+        Err(err) => {
+            con_print(
+                marker,
+                &format!(
+                    "Error reading {}, record the script with `_bxt_tas_recording_log_origins 1` \
+                     first: {}\n",
+                    origins_path.display(),
+                    err
+                ),
+            );
+            return;
+        }
+    };
+    let origins = match parse_origins(&origins) {
+        Ok(x) => x,
+        Err(line) => {
+            con_print(
+                marker,
+                &format!(
+                    "Error parsing {}: invalid line {}\n",
+                    origins_path.display(),
+                    line
+                ),
+            );
+            return;
+        }
+    };
+
+    *SELFTEST.borrow_mut(marker) = Some(SelfTest { origins, tick: 0 });
+    engine::prepend_command(
+        marker,
+        &format!("bxt_tas_loadscript \"{}\"\n", path.display()),
+    );
+    con_print(marker, "Self-test started\n");
+}
+
+/// Parses the `tick x y z pitch yaw roll` lines of an origins file.
+///
+/// Returns the number of the first invalid line on error.
+fn parse_origins(text: &str) -> Result<Vec<(u64, PlayerPosition)>, usize> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let mut fields = line.split_whitespace();
+            let tick = fields.next().and_then(|tick| tick.parse().ok());
+            let values: Option<Vec<f32>> = fields.map(|field| field.parse().ok()).collect();
+            match (tick, values.as_deref()) {
+                (Some(tick), Some(&[x, y, z, pitch, yaw, roll])) => Ok((
+                    tick,
+                    PlayerPosition {
+                        origin: [x, y, z],
+                        angles: [pitch, yaw, roll],
+                    },
+                )),
+                _ => Err(index + 1),
+            }
+        })
+        .collect()
+}
+
+fn tas_recording_mark(marker: MainThreadMarker, label: String) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    recorder.hltas.lines.push(Line::Comment(format!(
+        " MARK: {} @ frame {}",
+        label, recorder.tick
+    )));
+}
+
+fn tas_recording_stop_at(marker: MainThreadMarker, frame: u64) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    if frame > recorder.tick {
+        recorder.stop_at = Some(frame);
+        con_print(
+            marker,
+            &format!("The recording will stop at frame {}\n", frame),
+        );
+        return;
+    }
+
+    con_print(
+        marker,
+        &format!(
+            "Warning: the recording is already at frame {}, past frame {}; stopping the recording\n",
+            recorder.tick, frame
+        ),
+    );
+
+    request_stop(&mut state);
+
+    // Same as in stop_recording(), without a running server there are no frames to wait for.
+    // SAFETY: we're in a console command callback, so the server data isn't being modified.
+    if unsafe { engine::sv_active(marker) } != Some(true) {
+        if let Some(recorder) = take_stopped_recorder(&mut state) {
+            stop(marker, recorder);
+        }
+    }
+}
+
+fn tas_recording_validate(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let state = STATE.borrow(marker);
+    let recorder = match &*state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    let mut buf = Vec::new();
+    if let Err(err) = recorder.hltas.to_writer(&mut buf) {
+        con_print(marker, &format!("Error writing the script: {}\n", err));
+        return;
+    }
+
+    let script = match String::from_utf8(buf) {
+        Ok(x) => x,
+        Err(err) => {
+            con_print(marker, &format!("Error writing the script: {}\n", err));
+            return;
+        }
+    };
+
+    match HLTAS::from_str(&script) {
+        Ok(hltas) => con_print(
+            marker,
+            &format!("The script is valid, {} lines\n", hltas.lines.len()),
+        ),
+        Err(err) => con_print(marker, &format!("Error parsing the script: {}\n", err)),
+    }
+}
+
+fn tas_recording_dump_stdout(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let script = match recorded_script(marker) {
+        Some(script) => script,
+        None => {
+            con_print(marker, "No recording in progress\n");
+            return;
+        }
+    };
+
+    let mut stdout = io::stdout().lock();
+    if let Err(err) = stdout.write_all(&script).and_then(|()| stdout.flush()) {
+        con_print(marker, &format!("Error writing to stdout: {}\n", err));
+    }
+}
+
+/// Returns the script recorded so far serialized the same way as it would be written out.
+///
+/// Returns `None` if there's no recording in progress or if the script couldn't be serialized.
+pub fn recorded_script(marker: MainThreadMarker) -> Option<Vec<u8>> {
+    let state = STATE.borrow(marker);
+    let recorder = match &*state {
+        State::Recording(recorder) => recorder,
+        State::Idle => return None,
+    };
+
+    let mut hltas = recorder.hltas.clone();
+    drop_incomplete_frame_bulks(&mut hltas.lines);
+    transform_lines(marker, &mut hltas.lines);
+
+    let mut buf = Vec::new();
+    if let Err(err) = serialize_script(marker, &hltas, &mut buf) {
+        warn!("error serializing the recorded script: {}", err);
+        return None;
+    }
+
+    Some(buf)
+}
+
+fn tas_recording_mode(marker: MainThreadMarker, mode: Mode) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    match &mut *STATE.borrow_mut(marker) {
+        State::Recording(recorder) => recorder.mode = mode,
+        State::Idle => con_print(marker, "No recording in progress\n"),
+    }
+}
+
+fn tas_recording_status(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let state = STATE.borrow(marker);
+    let recorder = match &*state {
+        State::Recording(recorder) => recorder,
+        State::Idle => {
+            con_print(marker, "Status: idle\n");
+            return;
+        }
+    };
+
+    let stop_at = match recorder.stop_at {
+        Some(frame) => format!("Stopping at frame: {} (now {})\n", frame, recorder.tick),
+        None => String::new(),
+    };
+    let status = format!(
+        "Status: {}\n\
+         Output file: {}\n\
+         Lines: {}\n\
+         Pending frame times: {}\n\
+         Pending frame time remainders: {}\n\
+         {}",
+        if recorder.paused {
+            "recording (paused)"
+        } else {
+            "recording"
+        },
+        part_filename(&recorder.filename, recorder.split_count + 1).display(),
+        recorder.hltas.lines.len(),
+        recorder.pending_frame_times.len(),
+        recorder.pending_remainders.len(),
+        stop_at,
+    );
+
+    con_print(marker, &status);
+}
+
+fn tas_recording_from_demos(marker: MainThreadMarker, dir: PathBuf) {
+    if !TasRecording.is_enabled(marker)
+        || !engine::cls_demos.is_set(marker)
+        || !engine::com_gamedir.is_set(marker)
+        || !engine::Host_NextDemo.is_set(marker)
+    {
+        return;
+    }
+
+    if matches!(*STATE.borrow(marker), State::Recording(_)) {
+        con_print(marker, "Already recording\n");
+        return;
+    }
+
+    if BATCH.borrow(marker).is_some() {
+        con_print(marker, "Already converting demos\n");
+        return;
+    }
+
+    // SAFETY: com_gamedir is a null-terminated string.
+    let game_dir = PathBuf::from(
+        unsafe { CStr::from_ptr(engine::com_gamedir.get(marker).cast()) }
+            .to_string_lossy()
+            .into_owned(),
+    );
+
+    let entries = match game_dir.join(&dir).read_dir() {
+        Ok(entries) => entries,
+        Err(err) => {
+            con_print(marker, &format!("Error reading the directory: {}\n", err));
+            return;
+        }
+    };
+
+    let mut demos: Vec<_> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| matches!(path.extension(), Some(ext) if ext == "dem"))
+        .collect();
+
+    if demos.is_empty() {
+        con_print(marker, "Error: no demos found\n");
+        return;
+    }
+
+    // Play the demos in order by popping from the end.
+    demos.sort_unstable_by(|a, b| b.cmp(a));
+
+    con_print(marker, &format!("Converting {} demos\n", demos.len()));
+
+    *BATCH.borrow_mut(marker) = Some(Batch {
+        demos,
+        game_dir,
+        succeeded: 0,
+        failed: 0,
+    });
+
+    // SAFETY: we're in a console command callback, so the demos aren't being read.
+    unsafe { play_next_batch_demo(marker) };
+}
+
+/// Converts the records of a binary recording into a script.
+///
+/// The conversion is the same as for demos, as the records have the same data, with the frame
+/// time remainders and the 0 ms frames restored like in the recording.
+fn convert_records(records: &[binary::Record]) -> HLTAS {
+    let mut hltas = HLTAS::default();
+    let mut keys = Keys::default();
+
+    for record in records {
+        let cmd = usercmd_s {
+            lerp_msec: 0,
+            msec: record.msec,
+            viewangles: record.viewangles,
+            forwardmove: record.forwardmove,
+            sidemove: record.sidemove,
+            upmove: record.upmove,
+            lightlevel: 0,
+            buttons: record.buttons,
+            impulse: 0,
+            weaponselect: 0,
+            impact_index: 0,
+            impact_position: [0.; 3],
+        };
+
+        let (mut frame_bulk, moves) =
+            usercmd_to_frame_bulk(&mut keys, &cmd, false, false, record.movespeedkey);
+        frame_bulk.frame_time = format_frametime(record.frame_time);
+        let mut console_command = moves.commands.join(";");
+        push_remainder_command(&mut console_command, record.frametime_remainder);
+        if !console_command.is_empty() {
+            frame_bulk.console_command = Some(console_command);
+        }
+
+        hltas.lines.push(Line::FrameBulk(frame_bulk));
+        if record.msec == 0 {
+            mark_zero_ms_frame_bulk(&mut hltas);
+        }
+        keys.clear_impulses();
+    }
+
+    hltas
+}
+
+fn tas_recording_last_error(marker: MainThreadMarker) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let message = match &*LAST_ERROR.borrow(marker) {
+        Some(error) => format!("{}\n", error),
+        None => "none\n".to_owned(),
+    };
+    con_print(marker, &message);
+}
+
+fn tas_recording_convert(marker: MainThreadMarker, input: PathBuf, output: PathBuf) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let input = recording_path(marker, &input);
+    let records =
+        match File::open(&input).and_then(|file| binary::read_records(io::BufReader::new(file))) {
+            Ok(records) => records,
+            Err(err) => {
+                con_print(
+                    marker,
+                    &format!("Error reading {}: {}\n", input.display(), err),
+                );
+                return;
+            }
+        };
+
+    let output = recording_path(marker, &output);
+    if write_script(marker, convert_records(&records), &output) {
+        con_print(
+            marker,
+            &format!(
+                "Converted {} frames into {}\n",
+                records.len(),
+                output.display()
+            ),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::{Path, PathBuf};
+
+    use hltas::types::{AutoMovement, FrameBulk, Line};
+
+    use super::super::frames::{complete_frame, count_frame_time, uncount_frame_time};
+    use super::super::sink::PlayerPosition;
+    use super::super::{binary, restore_commands, Recorder};
+    use super::*;
+    use crate::ffi::buttons::Buttons;
+
+    #[test]
+    fn binary_conversion() {
+        let record = binary::Record {
+            frame_time: 0.01,
+            viewangles: [5., 90., 0.],
+            forwardmove: 0.,
+            sidemove: 0.,
+            upmove: 0.,
+            buttons: (Buttons::IN_FORWARD | Buttons::IN_JUMP).bits(),
+            msec: 10,
+            movespeedkey: 0.3,
+            frametime_remainder: 0.,
+        };
+        let analog = binary::Record {
+            frame_time: 0.001,
+            forwardmove: 250.,
+            buttons: 0,
+            msec: 1,
+            frametime_remainder: 0.25,
+            ..record
+        };
+        let zero_ms = binary::Record {
+            frame_time: 0.0001,
+            msec: 0,
+            ..record
+        };
+
+        let hltas = convert_records(&[record, analog, zero_ms]);
+
+        let mut expected = FrameBulk::with_frame_time("0.01".to_owned());
+        expected.auto_actions.movement = Some(AutoMovement::SetYaw(90.));
+        expected.pitch = Some(5.);
+        expected.movement_keys.forward = true;
+        expected.action_keys.jump = true;
+        expected.console_command = Some("cl_forwardspeed 0".to_owned());
+        let mut expected_analog = FrameBulk::with_frame_time("0.001".to_owned());
+        expected_analog.auto_actions.movement = Some(AutoMovement::SetYaw(90.));
+        expected_analog.pitch = Some(5.);
+        expected_analog.movement_keys.forward = true;
+        expected_analog.console_command =
+            Some("cl_forwardspeed 250;_bxt_set_frametime_remainder 0.25".to_owned());
+        let mut expected_zero_ms = expected.clone();
+        expected_zero_ms.frame_time = "0".to_owned();
+        assert_eq!(
+            hltas.lines,
+            [
+                Line::FrameBulk(expected),
+                Line::FrameBulk(expected_analog),
+                Line::FrameBulk(expected_zero_ms)
+            ]
+        );
+        assert_eq!(hltas.properties.frametime_0ms.as_deref(), Some("0.0001"));
+    }
+
+    #[test]
+    fn overwrite_path_expands_index_template() {
+        let dir = std::env::temp_dir().join(format!("bxt-rs-index-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("run_001.hltas"), "").unwrap();
+
+        let expanded = expand_recording_path(dir.join("run_%03d.hltas"), Path::exists);
+        let plain = expand_recording_path(dir.join("run.hltas"), Path::exists);
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(expanded, dir.join("run_002.hltas"));
+        assert_eq!(plain, dir.join("run.hltas"));
+    }
+
+    #[test]
+    fn index_templates() {
+        let existing = [Path::new("run_001.hltas"), Path::new("run_002.hltas")];
+        let exists = |path: &Path| existing.contains(&path);
+
+        assert_eq!(
+            expand_index_template(Path::new("run_%03d.hltas"), exists),
+            Some(PathBuf::from("run_003.hltas"))
+        );
+        assert_eq!(
+            expand_index_template(Path::new("run_%d.hltas"), exists),
+            Some(PathBuf::from("run_1.hltas"))
+        );
+        assert_eq!(expand_index_template(Path::new("run.hltas"), exists), None);
+        assert_eq!(
+            expand_index_template(Path::new("run_%3d.hltas"), exists),
+            None
+        );
+        assert_eq!(
+            expand_index_template(Path::new("run_%s.hltas"), exists),
+            None
+        );
+    }
+
+    #[test]
+    fn clean_mode() {
+        let value = |name: &str| match name {
+            "bxt_autojump" => Some("1".to_owned()),
+            _ => None,
+        };
+
+        // Variables that don't exist are left alone.
+        let (suspended, commands) = suspend_commands(value);
+        assert_eq!(suspended, [("bxt_autojump".to_owned(), "1".to_owned())]);
+        assert_eq!(commands, "bxt_autojump \"0\"\n");
+        assert_eq!(restore_commands(&suspended), "bxt_autojump \"1\"\n");
+
+        let (suspended, commands) = suspend_commands(|_| None);
+        assert!(suspended.is_empty());
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn origins_parsing() {
+        assert_eq!(
+            parse_origins("1 0 -16.5 36.03125 10 90 0\n\n3 1 2 3 0 0 0\n"),
+            Ok(vec![
+                (
+                    1,
+                    PlayerPosition {
+                        origin: [0., -16.5, 36.03125],
+                        angles: [10., 90., 0.],
+                    }
+                ),
+                (
+                    3,
+                    PlayerPosition {
+                        origin: [1., 2., 3.],
+                        angles: [0., 0., 0.],
+                    }
+                ),
+            ])
+        );
+        assert_eq!(parse_origins("1 0 0 0 0 0 0\n2 0 0 0\n"), Err(2));
+        assert_eq!(parse_origins("1 0 0 0 0 0 0 0\n"), Err(1));
+        assert_eq!(parse_origins("x 0 0 0 0 0 0\n"), Err(1));
+    }
+
+    #[test]
+    fn undo_frame_data() {
+        let filled = Line::FrameBulk(FrameBulk::with_frame_time("0.01".to_owned()));
+        let mut recorder = Recorder::default();
+        recorder.hltas.lines = vec![filled.clone(), filled.clone(), filled];
+        recorder.frame_count = 3;
+        count_frame_time(&mut recorder.frame_time_counts, 0.01);
+        count_frame_time(&mut recorder.frame_time_counts, 0.01);
+        count_frame_time(&mut recorder.frame_time_counts, 0.01);
+
+        assert_eq!(undo_frame_bulks(&mut recorder, 2), 2);
+        assert_eq!(recorder.hltas.lines.len(), 1);
+        assert_eq!(recorder.frame_count, 1);
+        assert_eq!(recorder.frame_time_counts, [("0.01".to_owned(), 1)]);
+        assert!(recorder.pending_frame_times.is_empty());
+        assert!(recorder.pending_remainders.is_empty());
+        assert!(recorder.pending_console_commands.is_empty());
+        assert!(recorder.pending_msecs.is_empty());
+
+        // The next frame with a single command.
+        let pending = |recorder: &mut Recorder, commands: usize| {
+            for _ in 0..commands {
+                recorder
+                    .hltas
+                    .lines
+                    .push(Line::FrameBulk(FrameBulk::with_frame_time(String::new())));
+            }
+            recorder.pending_frame_times.push(0.02);
+            recorder.pending_remainders.push(0.);
+            recorder.pending_console_commands.push(String::new());
+            recorder.pending_msecs.push(vec![20; commands]);
+        };
+        pending(&mut recorder, 1);
+        assert_eq!(complete_frame(&mut recorder), Ok(true));
+        assert_eq!(recorder.hltas.lines.len(), 2);
+
+        // The next frame with several commands, which are collapsed.
+        pending(&mut recorder, 2);
+        assert_eq!(complete_frame(&mut recorder), Ok(true));
+        let frame_times: Vec<_> = recorder
+            .hltas
+            .lines
+            .iter()
+            .filter_map(|line| match line {
+                Line::FrameBulk(frame_bulk) => Some(frame_bulk.frame_time.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(frame_times, ["0.01", "0.02", "0.02"]);
+        assert!(recorder.pending_frame_times.is_empty());
+
+        let mut counts = Vec::new();
+        count_frame_time(&mut counts, 0.01);
+        count_frame_time(&mut counts, 0.01);
+        count_frame_time(&mut counts, 0.02);
+        uncount_frame_time(&mut counts, "0.01");
+        uncount_frame_time(&mut counts, "0.02");
+        assert_eq!(counts, [("0.01".to_owned(), 1)]);
+    }
+}
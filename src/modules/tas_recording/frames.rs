@@ -0,0 +1,1428 @@
+//! Collection of the frame data from the engine and the server hooks.
+
+use std::ffi::CStr;
+use std::fmt::Write;
+use std::mem;
+use std::num::NonZeroU32;
+use std::os::raw::{c_char, c_int, c_void};
+
+use hltas::types::{
+    AutoMovement, FrameBulk, LeaveGroundAction, LeaveGroundActionSpeed, LeaveGroundActionType,
+    Line, StrafeSettings, Times,
+};
+use hltas::HLTAS;
+
+use super::transforms::drop_incomplete_frame_bulks;
+use super::{
+    action_key_buttons, add_split_action_keys, angle_anchor_command, angle_decimals,
+    apply_autojump, autosave, autostrafe, binary, change_map, check_selftest, convert_to_ducktap,
+    cvar_string, extra_button_commands, feed_sinks, finish, fire_comment, format_frametime,
+    is_crouch_jump, is_lgagst_jump, mark_teleport, mark_water_level, print_error, reconcile_speeds,
+    record_tick, remove_speed_commands, reset_seed, rng_set_command, round_angles,
+    shared_seed_change, stop, take_stopped_recorder, tracked_cvar_commands, usercmd_to_frame_bulk,
+    LgagstParameters, Mode, Pause, Recorder, State, BXT_TAS_RECORDING_DETECT_DUCKTAP,
+    BXT_TAS_RECORDING_DETECT_LGAGST, BXT_TAS_RECORDING_EMIT_SPEED_COMMANDS,
+    BXT_TAS_RECORDING_FIRE_COMMENTS, BXT_TAS_RECORDING_MAX_FRAMES, BXT_TAS_RECORDING_VERBOSE,
+    BXT_TAS_RECORDING_WATER_COMMENTS, STATE,
+};
+use crate::ffi::buttons::Buttons;
+use crate::ffi::edict;
+use crate::ffi::usercmd::usercmd_s;
+use crate::hooks::engine::{self, con_print, get_cvar_f32};
+use crate::hooks::server;
+use crate::utils::*;
+
+/// Buffers the frame time remainder of the current frame.
+///
+/// Within a host frame, the hooks are assumed to run in this order: `CL_Move()` (here), then
+/// `SV_Frame()` which calls [`on_sv_frame_start()`], then `CmdStart()` for every command the
+/// server runs, and finally [`on_sv_frame_end()`] which matches up the pending data from the end.
+///
+/// `CmdStart()` may only get hooked once the server library loads in the middle of the frame, so
+/// the remainder is buffered even before that. Without `CmdStart()` nothing consumes the pending
+/// remainders, so then only the latest one is kept.
+pub unsafe fn on_cl_move(marker: MainThreadMarker) {
+    // Demo playback is recorded from the demo data instead.
+    if is_playing_demo(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) if !recorder.paused => recorder,
+        _ => return,
+    };
+
+    // The sinks read the remainder along with the command.
+    if recorder.sinks_only {
+        return;
+    }
+
+    // The server doesn't run while disconnected, so this is the only hook that sees it happen.
+    let client_state = (*engine::cls.get(marker)).state;
+    if is_disconnect(recorder.last_client_state, client_state) {
+        mark_disconnect(recorder);
+    }
+
+    if client_state != 4 && client_state != 5 {
+        return;
+    }
+
+    if !server::CmdStart.is_set(marker) {
+        recorder.pending_remainders.clear();
+    }
+
+    recorder
+        .pending_remainders
+        .push(*engine::frametime_remainder.get(marker));
+}
+
+pub unsafe fn on_sv_frame_start(marker: MainThreadMarker) {
+    // Demo playback is recorded from the demo data instead.
+    if !server::CmdStart.is_set(marker) || is_playing_demo(marker) {
+        return;
+    }
+
+    check_selftest(marker);
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) if !recorder.paused => recorder,
+        _ => return,
+    };
+
+    let client_state = (*engine::cls.get(marker)).state;
+    if client_state == 5 && matches!(recorder.last_client_state, Some(last) if last != 5) {
+        recorder.map_started = true;
+    }
+    recorder.last_client_state = Some(client_state);
+
+    if client_state != 4 && client_state != 5 {
+        // The player can end up anywhere after a load.
+        recorder.last_origin = None;
+        return;
+    }
+
+    let on_ground = matches!(
+        engine::player_edict(marker),
+        // SAFETY: we're not calling any engine functions while the reference is alive.
+        Some(edict) if edict.as_ref().v.flags.contains(edict::Flags::FL_ONGROUND)
+    );
+    recorder.update_onground(on_ground);
+
+    let frame_time = recorded_frame_time(marker);
+    count_frame_time(&mut recorder.frame_time_counts, frame_time);
+
+    // The sinks get the frame time in the record and aren't split, so there's nothing else to
+    // keep track of.
+    if recorder.sinks_only {
+        recorder.tick += 1;
+        record_tick(marker, recorder);
+        return;
+    }
+
+    if let Some((map, _)) = engine::server_map_and_time(marker) {
+        match &recorder.current_map {
+            None => recorder.current_map = Some(map),
+            Some(current_map) if *current_map != map => change_map(marker, recorder, map),
+            Some(_) => (),
+        }
+    }
+
+    mark_teleport(marker, recorder);
+    if BXT_TAS_RECORDING_WATER_COMMENTS.as_bool(marker) {
+        mark_water_level(marker, recorder);
+    }
+
+    recorder.tick += 1;
+    record_tick(marker, recorder);
+    recorder.pending_frame_times.push(frame_time);
+    recorder.pending_msecs.push(Vec::new());
+
+    recorder
+        .pending_console_commands
+        .push(recorder.pending_bound_commands.join(";"));
+    recorder.pending_bound_commands.clear();
+}
+
+/// Returns the frame time of the current physics frame to record.
+///
+/// # Safety
+///
+/// It must be safe to call engine functions.
+unsafe fn recorded_frame_time(marker: MainThreadMarker) -> f64 {
+    // `host_frametime` is the `float` value of `host_framerate` converted to `double`, so read the
+    // locked frame time from the variable itself to record it exactly.
+    cvar_string(marker, "host_framerate")
+        .and_then(|value| locked_frame_time(&value))
+        .unwrap_or_else(|| *engine::host_frametime.get(marker))
+}
+
+/// Returns the frame time locked by `host_framerate` with the given value, if it's locked.
+fn locked_frame_time(host_framerate: &str) -> Option<f64> {
+    host_framerate
+        .trim()
+        .parse()
+        .ok()
+        .filter(|&frame_time: &f64| frame_time > 0.)
+}
+
+/// Returns whether the client got disconnected after running the recorded frames.
+///
+/// Unlike loads, which go through the connecting states, a disconnect drops the client state to
+/// `ca_disconnected`.
+fn is_disconnect(last_client_state: Option<c_int>, client_state: c_int) -> bool {
+    client_state <= 1 && matches!(last_client_state, Some(4 | 5))
+}
+
+/// Puts a boundary into the recording at a disconnect, so that a reconnect continues cleanly.
+///
+/// The frame bulks of the last frame won't get their frame times, and the pending frame data
+/// belongs to a server that's gone, so they're thrown away.
+fn mark_disconnect(recorder: &mut Recorder) {
+    drop_incomplete_frame_bulks(&mut recorder.hltas.lines);
+    recorder
+        .hltas
+        .lines
+        .push(Line::Comment(" Disconnected".to_owned()));
+
+    recorder.pending_frame_times.clear();
+    recorder.pending_msecs.clear();
+    recorder.pending_remainders.clear();
+    recorder.pending_bound_commands.clear();
+    recorder.pending_console_commands.clear();
+    recorder.pause = None;
+
+    // The reconnect starts the map anew, with its own angles and player state.
+    recorder.last_client_state = Some(1);
+    recorder.last_viewangles = None;
+    recorder.last_origin = None;
+    recorder.predicted_rng_state = None;
+}
+
+/// Records a command run by the server.
+///
+/// The command comes from the server's `CmdStart()`, so it's what the server received rather than
+/// what the client sent. It's recorded before the player movement code scales the moves down to the
+/// max speed: that clamping happens again when the script is replayed, so recording the clamped
+/// moves instead would apply it twice.
+pub unsafe fn on_cmd_start(marker: MainThreadMarker, cmd: usercmd_s, random_seed: u32) {
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) if !recorder.paused => recorder,
+        _ => return,
+    };
+
+    if !recorder.sinks.is_empty() {
+        let tick = recorder.tick;
+        feed_sinks(marker, &mut recorder.sinks, |sink| sink.on_cmd(tick, &cmd));
+        record_sink_cmd(marker, recorder, &cmd);
+    }
+    if recorder.sinks_only {
+        return;
+    }
+
+    if let Some(msecs) = recorder.pending_msecs.last_mut() {
+        msecs.push(cmd.msec);
+    }
+
+    if let Some(hltas::types::Line::FrameBulk(last_frame_bulk)) = recorder.hltas.lines.last_mut() {
+        if last_frame_bulk.frame_time.is_empty() && cmd.msec != 0 && !recorder.last_cmd_was_zero_ms
+        {
+            // This command is a part of a command-split sequence that we already made a frame bulk
+            // for.
+            add_split_action_keys(last_frame_bulk, Buttons::from_bits_truncate(cmd.buttons));
+            recorder.last_shared_seed = Some(random_seed);
+            return;
+        }
+    }
+
+    let client_state = (*engine::cls.get(marker)).state;
+    let is_paused = is_server_paused(marker);
+    if is_paused {
+        let pause = recorder.pause.get_or_insert(Pause {
+            client_state,
+            is_load: false,
+            command_count: 0,
+        });
+        pause.is_load |= pause.client_state != client_state;
+        pause.command_count += 1;
+        return;
+    }
+
+    // Loads go through the client connection states, while pausing the game doesn't touch them.
+    // The paused commands don't run any physics, but the replay has to wait through the pause
+    // too, so a pause is recorded as idle frame bulks between `setpause` and `unpause`. The
+    // player's own pause commands aren't recorded, so they don't toggle the pause a second time.
+    let mut was_loading = false;
+    let mut unpaused = false;
+    if let Some(pause) = recorder.pause.take() {
+        if pause.is_load || pause.client_state != client_state {
+            was_loading = true;
+        } else {
+            recorder
+                .hltas
+                .lines
+                .push(hltas::types::Line::Comment(format!(
+                    " Paused for {} commands",
+                    pause.command_count
+                )));
+            // The pending frame data of the paused frames is still there, so these get their
+            // frame times along with the frame bulk of this command.
+            push_pause_frame_bulks(&mut recorder.hltas.lines, pause.command_count);
+            unpaused = true;
+        }
+    }
+
+    // Take the initial seed from the first command that runs physics, so that starting the
+    // recording during a load gets the seed right.
+    if recorder.hltas.properties.seeds.is_none() {
+        recorder.hltas.properties.seeds = Some(hltas::types::Seeds {
+            shared: random_seed,
+            non_shared: 1337,
+        });
+    }
+
+    if mem::take(&mut recorder.map_started) {
+        if let Some(seed) = engine::rng_state(marker).and_then(reset_seed) {
+            recorder.hltas.lines.push(Line::Reset {
+                non_shared_seed: seed,
+            });
+        }
+    }
+
+    let is_first_command = recorder.last_shared_seed.is_none();
+    if let Some(seed) = shared_seed_change(recorder.last_shared_seed, random_seed, was_loading) {
+        recorder
+            .hltas
+            .lines
+            .push(hltas::types::Line::SharedSeed(seed));
+    }
+
+    recorder.last_cmd_was_zero_ms = cmd.msec == 0;
+    recorder.last_shared_seed = Some(random_seed);
+
+    let (mut frame_bulk, moves) = usercmd_to_frame_bulk(
+        &mut recorder.keys,
+        &cmd,
+        recorder.up_held,
+        recorder.down_held,
+        get_cvar_f32(marker, "cl_movespeedkey").unwrap_or(0.3),
+    );
+    round_angles(&mut frame_bulk, angle_decimals(marker));
+    let mut commands = moves.commands;
+    if BXT_TAS_RECORDING_EMIT_SPEED_COMMANDS.as_bool(marker) {
+        reconcile_speeds(&mut commands, |name| get_cvar_f32(marker, name));
+    } else {
+        remove_speed_commands(&mut commands);
+    }
+    if moves.approximated {
+        recorder.hltas.lines.push(Line::Comment(
+            " The movement of the next frame is approximated".to_owned(),
+        ));
+    }
+
+    if recorder.mode == Mode::Strafe {
+        if let Some(strafe) = detect_strafe(marker, &cmd) {
+            // Strafing computes the horizontal movement on its own.
+            frame_bulk.auto_actions.movement = Some(AutoMovement::Strafe(strafe));
+            frame_bulk.movement_keys.forward = false;
+            frame_bulk.movement_keys.back = false;
+            frame_bulk.movement_keys.left = false;
+            frame_bulk.movement_keys.right = false;
+            commands.retain(|command| command.starts_with("cl_upspeed "));
+        }
+    }
+
+    let attack = frame_bulk.action_keys.attack_1;
+    if BXT_TAS_RECORDING_FIRE_COMMENTS.as_bool(marker) {
+        recorder.hltas.lines.extend(fire_comment(
+            attack,
+            recorder.last_cmd_attack,
+            recorder.tick,
+        ));
+    }
+    recorder.last_cmd_attack = attack;
+
+    let jump = frame_bulk.action_keys.jump;
+    if is_crouch_jump(
+        (frame_bulk.action_keys.duck, jump),
+        (recorder.duck_tracker.held_last, recorder.last_cmd_jump),
+    ) {
+        // The action keys are always written in the same order, so the comment only marks the
+        // frame for whoever edits the script.
+        recorder
+            .hltas
+            .lines
+            .push(Line::Comment(" Crouch-jump".to_owned()));
+    }
+
+    if jump && BXT_TAS_RECORDING_DETECT_LGAGST.as_bool(marker) {
+        if let Some(edict) = engine::player_edict(marker) {
+            // SAFETY: we're not calling any engine functions while the reference is alive.
+            let edict = edict.as_ref();
+            let speed = edict.v.velocity[0].hypot(edict.v.velocity[1]);
+
+            let mut max_speed = get_cvar_f32(marker, "sv_maxspeed").unwrap_or(320.);
+            if edict.v.maxspeed != 0. {
+                max_speed = max_speed.min(edict.v.maxspeed);
+            }
+            let parameters = LgagstParameters {
+                frame_time: f32::from(cmd.msec) / 1000.,
+                max_speed,
+                accelerate: get_cvar_f32(marker, "sv_accelerate").unwrap_or(10.),
+                air_accelerate: get_cvar_f32(marker, "sv_airaccelerate").unwrap_or(10.),
+                friction: get_cvar_f32(marker, "sv_friction").unwrap_or(4.),
+                stop_speed: get_cvar_f32(marker, "sv_stopspeed").unwrap_or(100.),
+                ent_friction: edict.v.friction,
+            };
+
+            if is_lgagst_jump(
+                parameters,
+                recorder.is_onground,
+                recorder.last_cmd_jump,
+                speed,
+            ) {
+                frame_bulk.action_keys.jump = false;
+                frame_bulk.auto_actions.leave_ground_action = Some(LeaveGroundAction {
+                    speed: LeaveGroundActionSpeed::Optimal,
+                    times: Times::Limited(NonZeroU32::new(1).unwrap()),
+                    type_: LeaveGroundActionType::Jump,
+                });
+            }
+        }
+    }
+    recorder.last_cmd_jump = jump;
+
+    // With Bunnymod XT's autojump, holding jump jumps on every landing, which a held jump key
+    // wouldn't replay.
+    if jump && matches!(get_cvar_f32(marker, "bxt_autojump"), Some(value) if value != 0.) {
+        apply_autojump(&mut frame_bulk);
+    }
+
+    // The ducktap is known only once the duck is released, so it's applied to the previous frame,
+    // and it's the ground state of the previous frame that matters.
+    let is_ducktap = recorder
+        .duck_tracker
+        .update(frame_bulk.action_keys.duck, recorder.was_onground);
+    if is_ducktap && BXT_TAS_RECORDING_DETECT_DUCKTAP.as_bool(marker) {
+        convert_to_ducktap(&mut recorder.hltas.lines);
+    }
+
+    // Before the speeds of the frame itself, which can differ from the starting ones.
+    commands.splice(0..0, mem::take(&mut recorder.snapshot_commands));
+
+    if unpaused {
+        commands.insert(0, "unpause".to_owned());
+    }
+
+    commands.extend(extra_button_commands(
+        &recorder.extra_buttons,
+        cmd.buttons,
+        recorder.last_buttons,
+    ));
+    recorder.last_buttons = cmd.buttons;
+
+    commands.extend(angle_anchor_command(marker, recorder, cmd.viewangles));
+
+    let predicted_rng_state = recorder.predicted_rng_state.take();
+    if let Some(rng_state) = engine::rng_state(marker) {
+        commands.extend(rng_set_command(
+            is_first_command,
+            predicted_rng_state,
+            rng_state,
+        ));
+    }
+
+    commands.extend(tracked_cvar_commands(marker, &mut recorder.tracked_cvars));
+
+    // TODO: confirming selection in invnext, invprev.
+
+    frame_bulk.console_command = Some(commands.join(";"));
+
+    if BXT_TAS_RECORDING_VERBOSE.as_bool(marker) {
+        info!(
+            "tick {}: buttons {:?}, forwardmove {}, sidemove {}, upmove {} -> keys {:?}, \
+             command {:?}",
+            recorder.tick,
+            Buttons::from_bits_truncate(cmd.buttons),
+            cmd.forwardmove,
+            cmd.sidemove,
+            cmd.upmove,
+            frame_bulk.movement_keys,
+            frame_bulk.console_command.as_deref().unwrap_or(""),
+        );
+    }
+
+    recorder
+        .hltas
+        .lines
+        .push(hltas::types::Line::FrameBulk(frame_bulk));
+
+    recorder.keys.clear_impulses();
+
+    recorder.frame_count += 1;
+    let max_frames = BXT_TAS_RECORDING_MAX_FRAMES.as_u64(marker);
+    if max_frames > 0 && recorder.frame_count >= max_frames && !recorder.stop_requested {
+        // Stopped at the end of the frame, once the frame bulks have their frame times.
+        recorder.stop_requested = true;
+        con_print(
+            marker,
+            &format!(
+                "Warning: reached the limit of {} recorded frames set by \
+                 _bxt_tas_recording_max_frames; stopping the recording\n",
+                max_frames
+            ),
+        );
+    }
+}
+
+/// Pushes the idle frame bulks replaying a pause which lasted for `count` commands.
+///
+/// The frame bulks keep the keys and the view angles of the last frame bulk, so that the held keys
+/// aren't released and pressed again over the pause. The first one pauses the game.
+fn push_pause_frame_bulks(lines: &mut Vec<Line>, count: u32) {
+    let mut frame_bulk = FrameBulk::with_frame_time(String::new());
+    let last = lines.iter().rev().find_map(|line| match line {
+        Line::FrameBulk(frame_bulk) => Some(frame_bulk),
+        _ => None,
+    });
+    if let Some(last) = last {
+        frame_bulk.movement_keys = last.movement_keys;
+        frame_bulk.action_keys = last.action_keys;
+        frame_bulk.pitch = last.pitch;
+        if let Some(AutoMovement::SetYaw(yaw)) = last.auto_actions.movement {
+            frame_bulk.auto_actions.movement = Some(AutoMovement::SetYaw(yaw));
+        }
+    }
+
+    for i in 0..count {
+        let mut frame_bulk = frame_bulk.clone();
+        if i == 0 {
+            frame_bulk.console_command = Some("setpause".to_owned());
+        }
+        lines.push(Line::FrameBulk(frame_bulk));
+    }
+}
+
+/// Returns whether a demo is being played back.
+unsafe fn is_playing_demo(marker: MainThreadMarker) -> bool {
+    engine::cls_demos.is_set(marker) && (*engine::cls_demos.get(marker)).demoplayback != 0
+}
+
+/// Records a frame of demo playback from the client data stored in the demo.
+///
+/// `cdat` points to the engine's `client_data_t`, which starts with the origin followed by the
+/// view angles.
+pub unsafe fn on_demo_update_client_data(marker: MainThreadMarker, cdat: *mut c_void) {
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) if !recorder.paused => recorder,
+        _ => return,
+    };
+
+    if !is_playing_demo(marker) {
+        return;
+    }
+
+    let frame_time = *engine::host_frametime.get(marker);
+    let cmd = usercmd_s {
+        lerp_msec: 0,
+        msec: (frame_time * 1000.).round().clamp(0., 255.) as u8,
+        viewangles: *cdat.cast::<[f32; 3]>().add(1),
+        forwardmove: 0.,
+        sidemove: 0.,
+        upmove: 0.,
+        lightlevel: 0,
+        buttons: 0,
+        impulse: 0,
+        weaponselect: 0,
+        impact_index: 0,
+        impact_position: [0.; 3],
+    };
+
+    record_demo_cmd(marker, recorder, &cmd, frame_time);
+}
+
+/// Returns whether the server is paused.
+///
+/// The module requires the offset of the paused flag, so it's known while recording. Warns and
+/// assumes not paused if it's missing anyway, for example if the pointers were reset.
+///
+/// # Safety
+///
+/// `sv` must be valid to read from.
+unsafe fn is_server_paused(marker: MainThreadMarker) -> bool {
+    engine::sv_paused(marker).unwrap_or_else(|| {
+        warn!("the server paused flag is unknown, assuming not paused");
+        false
+    })
+}
+
+/// Records a command into the record of the current frame for the sinks.
+///
+/// Like with scripts, the commands of a command-split sequence are merged into one record, with the
+/// action buttons of all of them.
+unsafe fn record_sink_cmd(marker: MainThreadMarker, recorder: &mut Recorder, cmd: &usercmd_s) {
+    // The paused commands don't run any physics.
+    if is_server_paused(marker) {
+        return;
+    }
+
+    if let Some(record) = &mut recorder.pending_record {
+        let action_buttons = Buttons::IN_JUMP
+            | Buttons::IN_DUCK
+            | Buttons::IN_USE
+            | Buttons::IN_ATTACK
+            | Buttons::IN_ATTACK2
+            | Buttons::IN_RELOAD;
+        record.buttons |= cmd.buttons & action_buttons.bits();
+        return;
+    }
+
+    recorder.pending_record = Some(binary::Record {
+        frame_time: recorded_frame_time(marker),
+        viewangles: cmd.viewangles,
+        forwardmove: cmd.forwardmove,
+        sidemove: cmd.sidemove,
+        upmove: cmd.upmove,
+        buttons: cmd.buttons,
+        msec: cmd.msec,
+        movespeedkey: get_cvar_f32(marker, "cl_movespeedkey").unwrap_or(0.3),
+        // `CL_Move()` of this frame has already run, so this is the remainder of the frame.
+        frametime_remainder: *engine::frametime_remainder.get(marker),
+    });
+}
+
+/// Records a command played back from a demo.
+///
+/// Demo playback has no server running, so unlike live commands, the shared seed, the RNG state and
+/// the player state aren't recorded. Of the command fields, only `viewangles` and `msec` are
+/// reliable during demo playback: the movement values, the buttons, the impulse and the weapon
+/// selection aren't available and are expected to be zero, which records them as not pressed.
+fn record_demo_cmd(
+    marker: MainThreadMarker,
+    recorder: &mut Recorder,
+    cmd: &usercmd_s,
+    frame_time: f64,
+) {
+    let (mut frame_bulk, mut moves) =
+        usercmd_to_frame_bulk(&mut recorder.keys, cmd, false, false, 0.);
+    round_angles(&mut frame_bulk, angle_decimals(marker));
+    frame_bulk.frame_time = format_frametime(frame_time);
+    moves
+        .commands
+        .extend(angle_anchor_command(marker, recorder, cmd.viewangles));
+    if !moves.commands.is_empty() {
+        frame_bulk.console_command = Some(moves.commands.join(";"));
+    }
+
+    recorder.hltas.lines.push(Line::FrameBulk(frame_bulk));
+    recorder.keys.clear_impulses();
+}
+
+/// Returns the strafe settings reproducing the command if the player is air strafing.
+unsafe fn detect_strafe(marker: MainThreadMarker, cmd: &usercmd_s) -> Option<StrafeSettings> {
+    // SAFETY: we're not calling any engine functions while the reference is alive.
+    let edict = engine::player_edict(marker)?.as_ref();
+
+    if edict.v.flags.contains(edict::Flags::FL_ONGROUND) || edict.v.waterlevel >= 2 {
+        return None;
+    }
+
+    let mut max_speed = get_cvar_f32(marker, "sv_maxspeed").unwrap_or(320.);
+    if edict.v.maxspeed != 0. {
+        max_speed = max_speed.min(edict.v.maxspeed);
+    }
+
+    let mut forwardmove = cmd.forwardmove;
+    let mut sidemove = cmd.sidemove;
+    if edict.v.flags.contains(edict::Flags::FL_DUCKING) {
+        forwardmove *= 0.333;
+        sidemove *= 0.333;
+    }
+
+    let parameters = autostrafe::Parameters {
+        frame_time: f32::from(cmd.msec) / 1000.,
+        max_speed,
+        air_accelerate: get_cvar_f32(marker, "sv_airaccelerate").unwrap_or(10.),
+        ent_friction: edict.v.friction,
+    };
+
+    autostrafe::detect(
+        parameters,
+        [edict.v.velocity[0], edict.v.velocity[1]],
+        cmd.viewangles[1],
+        forwardmove,
+        sidemove,
+    )
+}
+
+pub unsafe fn on_sv_frame_end(marker: MainThreadMarker) {
+    let mut state = STATE.borrow_mut(marker);
+
+    if let State::Recording(recorder) = &mut *state {
+        if let Some(record) = recorder.pending_record.take() {
+            feed_sinks(marker, &mut recorder.sinks, |sink| sink.on_frame(&record));
+        }
+
+        if !recorder.paused {
+            match complete_frame(recorder) {
+                Ok(had_cmd) => {
+                    recorder.predicted_rng_state = engine::rng_state(marker);
+
+                    // Every frame bulk has its frame time at this point, so the script is
+                    // complete.
+                    if had_cmd {
+                        autosave(marker, recorder);
+                    }
+                }
+                Err(MissingConsoleCommands) => {
+                    print_error(
+                        marker,
+                        &format!(
+                            "Stopping the recording: the pending frame data is out of sync ({} \
+                             frame times, {} remainders, {} console commands)\n",
+                            recorder.pending_frame_times.len(),
+                            recorder.pending_remainders.len(),
+                            recorder.pending_console_commands.len(),
+                        ),
+                    );
+
+                    // Save what was recorded up to the broken frame rather than losing
+                    // everything.
+                    if let State::Recording(recorder) = mem::replace(&mut *state, State::Idle) {
+                        finish(marker, recorder);
+                    }
+                    return;
+                }
+            }
+
+            if matches!(recorder.stop_at, Some(frame) if recorder.tick >= frame) {
+                con_print(marker, &format!("Reached frame {}\n", recorder.tick));
+                recorder.stop_requested = true;
+            }
+        }
+    }
+
+    // Checked after the frame times are filled in, so that the frame bulks of this frame are
+    // written out complete. A paused recording can be stopped too.
+    if let Some(recorder) = take_stopped_recorder(&mut state) {
+        stop(marker, recorder);
+    }
+}
+
+/// Fills in the frame times of the frame bulks of the frame that just ended.
+///
+/// Returns whether there were frame bulks to fill, clearing the pending frame data if so.
+pub(super) fn complete_frame(recorder: &mut Recorder) -> Result<bool, MissingConsoleCommands> {
+    if let Some(msecs) = recorder.pending_msecs.last() {
+        insert_split_comment(&mut recorder.hltas.lines, msecs);
+    }
+
+    let had_cmd = fill_frame_bulks(
+        &mut recorder.hltas.lines,
+        &recorder.pending_frame_times,
+        &recorder.pending_remainders,
+        &recorder.pending_console_commands,
+    )?;
+
+    if had_cmd {
+        if recorder.last_cmd_was_zero_ms {
+            mark_zero_ms_frame_bulk(&mut recorder.hltas);
+        }
+
+        recorder.pending_frame_times.clear();
+        recorder.pending_msecs.clear();
+        recorder.pending_console_commands.clear();
+        recorder.pending_remainders.clear();
+    }
+
+    Ok(had_cmd)
+}
+
+/// Counts the frame time in the frame time histogram.
+pub(super) fn count_frame_time(counts: &mut Vec<(String, u64)>, frame_time: f64) {
+    // Formatted the same way as in the script, so that floating-point noise doesn't count as a
+    // different frame time.
+    let frame_time = format_frametime(frame_time);
+    match counts.iter_mut().find(|(x, _)| *x == frame_time) {
+        Some((_, count)) => *count += 1,
+        None => counts.push((frame_time, 1)),
+    }
+}
+
+/// Takes back one count of the frame time from the frame time histogram.
+pub(super) fn uncount_frame_time(counts: &mut Vec<(String, u64)>, frame_time: &str) {
+    if let Some(index) = counts.iter().position(|(x, _)| x == frame_time) {
+        counts[index].1 -= 1;
+        if counts[index].1 == 0 {
+            counts.remove(index);
+        }
+    }
+}
+
+/// Marks the frame bulk of the last frame if the frame was split into several commands.
+///
+/// The commands of a split frame are recorded as a single frame bulk with the whole frame time,
+/// so the comment keeps the `msec` of every command for reproducing the split exactly.
+fn insert_split_comment(lines: &mut Vec<Line>, msecs: &[u8]) {
+    if msecs.iter().filter(|&&msec| msec != 0).count() < 2 {
+        return;
+    }
+
+    let index = lines.iter().rposition(
+        |line| matches!(line, Line::FrameBulk(frame_bulk) if frame_bulk.frame_time.is_empty()),
+    );
+    if let Some(index) = index {
+        let msecs: Vec<String> = msecs.iter().map(u8::to_string).collect();
+        lines.insert(
+            index,
+            Line::Comment(format!(" Command split: {} ms", msecs.join(" + "))),
+        );
+    }
+}
+
+/// Fills the frame times of the frame bulks recorded since the last physics frames.
+///
+/// `frame_times`, `remainders` and `console_commands` hold the data of the pending physics frames,
+/// aligned to the last frame. With 0 ms frames, we might have built up a few "unused" frame times,
+/// so the frame bulks take the data of the last frames and the rest is discarded. If there are more
+/// frame bulks than frames, several commands ran in the same frame: such frame bulks are collapsed
+/// into a single one with the last command's movement, and the action keys and the console commands
+/// of all of them.
+///
+/// Returns `false` if there were no frame bulks to fill. If some frames are missing their console
+/// commands, nothing is filled and an error is returned.
+pub(super) fn fill_frame_bulks(
+    lines: &mut Vec<Line>,
+    frame_times: &[f64],
+    remainders: &[f64],
+    console_commands: &[String],
+) -> Result<bool, MissingConsoleCommands> {
+    let mut indices: Vec<usize> = lines
+        .iter()
+        .enumerate()
+        .rev()
+        .filter_map(|(index, line)| match line {
+            Line::FrameBulk(frame_bulk) => Some((index, frame_bulk)),
+            _ => None,
+        })
+        .take_while(|(_, frame_bulk)| frame_bulk.frame_time.is_empty())
+        .map(|(index, _)| index)
+        .collect();
+    indices.reverse();
+
+    if indices.is_empty() || frame_times.is_empty() {
+        return Ok(false);
+    }
+
+    // Console commands are pushed along with the frame times, so this shouldn't happen, but check
+    // before modifying anything so that the recording can be saved as is.
+    if console_commands.len() < indices.len().min(frame_times.len()) {
+        return Err(MissingConsoleCommands);
+    }
+
+    if indices.len() > frame_times.len() {
+        let extra = indices.len() - frame_times.len();
+        let target = indices[extra];
+
+        let mut merged: Vec<String> = Vec::new();
+        let mut buttons = Buttons::empty();
+        for &index in &indices[..=extra] {
+            if let Line::FrameBulk(frame_bulk) = &lines[index] {
+                // Keys pressed only on the collapsed commands still have to be pressed on this
+                // frame, or the replay would miss them and, for example, reload cancels would lose
+                // their timing.
+                if index != target {
+                    buttons.insert(action_key_buttons(&frame_bulk.action_keys));
+                }
+
+                if let Some(command) = &frame_bulk.console_command {
+                    if !command.is_empty() {
+                        merged.push(command.clone());
+                    }
+                }
+            }
+        }
+        if let Line::FrameBulk(frame_bulk) = &mut lines[target] {
+            frame_bulk.console_command = Some(merged.join(";"));
+            add_split_action_keys(frame_bulk, buttons);
+        }
+
+        for &index in indices[..extra].iter().rev() {
+            lines.remove(index);
+        }
+
+        // Everything up to the target shifted back by the number of removed lines.
+        indices = indices[extra..].iter().map(|index| index - extra).collect();
+    }
+
+    let first_frame = frame_times.len() - indices.len();
+    for (frame, &index) in (first_frame..).zip(&indices) {
+        let frame_bulk = match &mut lines[index] {
+            Line::FrameBulk(frame_bulk) => frame_bulk,
+            _ => unreachable!(),
+        };
+
+        // Frames counted from the last one, to index the other pending data.
+        let from_end = frame_times.len() - frame;
+
+        frame_bulk.frame_time = format_frametime(frame_times[frame]);
+
+        let console_command = frame_bulk.console_command.get_or_insert_with(String::new);
+        // A remainder can be missing for the first frames of the recording if `CL_Move()` ran
+        // before the recording could see it. Assume the usual zero remainder then.
+        let remainder = remainders
+            .len()
+            .checked_sub(from_end)
+            .map_or(0., |index| remainders[index]);
+        push_remainder_command(console_command, remainder);
+
+        let player_command = &console_commands[console_commands.len() - from_end];
+        if !player_command.is_empty() {
+            // Prepend player commands so they don't override ours.
+            *console_command = if console_command.is_empty() {
+                player_command.clone()
+            } else {
+                format!("{};{}", player_command, console_command)
+            };
+        }
+
+        if frame_bulk.console_command.as_deref() == Some("") {
+            frame_bulk.console_command = None;
+        }
+    }
+
+    Ok(true)
+}
+
+/// Appends the command setting the frame time remainder, unless the remainder is zero.
+pub(super) fn push_remainder_command(console_command: &mut String, remainder: f64) {
+    // The remainder is zero most of the time, with constant frame times.
+    if remainder == 0. {
+        return;
+    }
+
+    if !console_command.is_empty() {
+        console_command.push(';');
+    }
+    // Unlike the frame time, the remainder is the accumulated floating-point error itself, so it has
+    // to be exact. `Display` for `f64` prints the shortest decimal which parses back into the same
+    // value, without the exponent notation.
+    write!(
+        console_command,
+        "_bxt_set_frametime_remainder {}",
+        remainder
+    )
+    .expect("writing to `String` should never error");
+}
+
+/// Turns the last frame bulk into a 0 ms frame bulk.
+///
+/// 0 ms frame bulks have a frame time of `0` and run with the frame time from the `frametime0ms`
+/// property, which is set from the first 0 ms frame.
+pub(super) fn mark_zero_ms_frame_bulk(hltas: &mut HLTAS) {
+    let frame_bulk = hltas.lines.iter_mut().rev().find_map(|line| match line {
+        Line::FrameBulk(frame_bulk) => Some(frame_bulk),
+        _ => None,
+    });
+
+    if let Some(frame_bulk) = frame_bulk {
+        let frame_time = mem::replace(&mut frame_bulk.frame_time, "0".to_owned());
+        hltas.properties.frametime_0ms.get_or_insert(frame_time);
+    }
+}
+
+/// Error of frames missing their console commands in [`fill_frame_bulks()`].
+#[derive(Debug, PartialEq, Eq)]
+pub(super) struct MissingConsoleCommands;
+
+static INSIDE_KEY_EVENT: MainThreadCell<bool> = MainThreadCell::new(false);
+
+pub fn on_key_event_start(marker: MainThreadMarker) {
+    INSIDE_KEY_EVENT.set(marker, true);
+}
+
+pub fn on_key_event_end(marker: MainThreadMarker) {
+    INSIDE_KEY_EVENT.set(marker, false);
+}
+
+/// Records the commands issued by the player.
+///
+/// This covers both key binds and commands typed into the console, as the console submits its
+/// input from within `Key_Event()` too. Commands executed by scripts, aliases and the engine itself
+/// are added outside of key events and aren't recorded, since the commands that issued them
+/// already are.
+pub unsafe fn on_cbuf_addtext(marker: MainThreadMarker, text: *const c_char) {
+    if !INSIDE_KEY_EVENT.get(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    let recorder = match &mut *state {
+        State::Recording(recorder) => recorder,
+        State::Idle => return,
+    };
+
+    let text = match CStr::from_ptr(text).to_str() {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+
+    recorder.record_bound_text(text);
+}
+
+/// Splits console text into separate commands the same way the engine does.
+///
+/// Commands are separated by newlines and by semicolons outside of quotes.
+pub(super) fn split_commands(text: &str) -> impl Iterator<Item = &str> {
+    let mut in_quotes = false;
+    text.split(move |c| {
+        if c == '"' {
+            in_quotes = !in_quotes;
+        }
+
+        c == '\n' || (c == ';' && !in_quotes)
+    })
+    .map(str::trim)
+    .filter(|command| !command.is_empty())
+}
+
+/// Returns whether the command with this name should be recorded into the script.
+pub(super) fn is_recorded_command(name: &str) -> bool {
+    // Ignore commands that we handle with frame bulk inputs. `+speed` is recorded from the walk
+    // button of the command.
+    if let Some(name) = name.strip_prefix(&['+', '-'][..]) {
+        if matches!(
+            name,
+            "forward"
+                | "back"
+                | "moveright"
+                | "moveleft"
+                | "moveup"
+                | "movedown"
+                | "jump"
+                | "duck"
+                | "use"
+                | "attack"
+                | "attack2"
+                | "reload"
+                | "left"
+                | "right"
+                | "lookup"
+                | "lookdown"
+                | "speed"
+        ) {
+            return false;
+        }
+    }
+
+    // Pauses are recorded with their own `setpause` and `unpause`. The commands ending a pause
+    // arrive during the pause, so recording them would leave the game paused on playback.
+    if matches!(name, "pause" | "setpause" | "unpause") {
+        return false;
+    }
+
+    // Ignore the commands controlling the recording itself.
+    !name.starts_with("bxt_tas_recording_")
+}
+
+#[cfg(test)]
+mod tests {
+    use hltas::types::{AutoMovement, FrameBulk, Line, Properties};
+    use hltas::HLTAS;
+
+    use super::super::tests::{complete, incomplete};
+    use super::super::{request_stop, take_stopped_recorder, Recorder, State};
+    use super::*;
+
+    #[test]
+    fn split_commands_like_engine() {
+        let commands: Vec<_> = split_commands("say \"a;b\"; impulse 101\n\n;wait;").collect();
+        assert_eq!(commands, ["say \"a;b\"", "impulse 101", "wait"]);
+    }
+
+    #[test]
+    fn pause_frame_bulks() {
+        let mut last = FrameBulk::with_frame_time("0.01".to_owned());
+        last.movement_keys.forward = true;
+        last.action_keys.duck = true;
+        last.pitch = Some(5.);
+        last.auto_actions.movement = Some(AutoMovement::SetYaw(90.));
+        last.console_command = Some("cl_forwardspeed 400".to_owned());
+        let mut lines = vec![Line::FrameBulk(last)];
+
+        push_pause_frame_bulks(&mut lines, 2);
+
+        let mut idle = FrameBulk::with_frame_time(String::new());
+        idle.movement_keys.forward = true;
+        idle.action_keys.duck = true;
+        idle.pitch = Some(5.);
+        idle.auto_actions.movement = Some(AutoMovement::SetYaw(90.));
+        let mut pause = idle.clone();
+        pause.console_command = Some("setpause".to_owned());
+        assert_eq!(lines[1..], [Line::FrameBulk(pause), Line::FrameBulk(idle)]);
+
+        // The idle frame bulks get the frame times of the paused frames.
+        fill_frame_bulks(
+            &mut lines,
+            &[0.01, 0.02, 0.03],
+            &[0., 0., 0.],
+            &["".to_owned(), "".to_owned(), "".to_owned()],
+        )
+        .unwrap();
+        assert!(
+            matches!(&lines[1], Line::FrameBulk(frame_bulk) if frame_bulk.frame_time == "0.02")
+        );
+        assert!(
+            matches!(&lines[2], Line::FrameBulk(frame_bulk) if frame_bulk.frame_time == "0.03")
+        );
+    }
+
+    #[test]
+    fn recorded_commands() {
+        assert!(is_recorded_command("impulse"));
+        assert!(!is_recorded_command("+speed"));
+        assert!(is_recorded_command("+forwardx"));
+        assert!(!is_recorded_command("+forward"));
+        assert!(!is_recorded_command("-moveup"));
+        assert!(!is_recorded_command("bxt_tas_recording_stop"));
+        assert!(!is_recorded_command("pause"));
+    }
+
+    #[test]
+    fn stop_requested_during_frame() {
+        let mut state = State::Idle;
+        assert!(!request_stop(&mut state));
+        assert!(take_stopped_recorder(&mut state).is_none());
+
+        state = State::Recording(Recorder::default());
+        assert!(take_stopped_recorder(&mut state).is_none());
+        assert!(matches!(state, State::Recording(_)));
+
+        // The stop command during the frame only marks the recorder.
+        assert!(request_stop(&mut state));
+        assert!(matches!(&state, State::Recording(recorder) if recorder.stop_requested));
+
+        // The end of the frame then takes it out.
+        assert!(take_stopped_recorder(&mut state).is_some());
+        assert!(matches!(state, State::Idle));
+        assert!(take_stopped_recorder(&mut state).is_none());
+    }
+
+    #[test]
+    fn stop_completes_the_frame() {
+        // The stop command runs before the frame, whose command then makes a frame bulk.
+        let mut state = State::Recording(Recorder {
+            hltas: HLTAS {
+                lines: vec![complete("0.01", "")],
+                ..HLTAS::default()
+            },
+            ..Recorder::default()
+        });
+        assert!(request_stop(&mut state));
+
+        if let State::Recording(recorder) = &mut state {
+            recorder.hltas.lines.push(incomplete(""));
+            recorder.pending_frame_times.push(0.02);
+            recorder.pending_console_commands.push(String::new());
+
+            assert_eq!(complete_frame(recorder), Ok(true));
+            assert!(recorder.pending_frame_times.is_empty());
+        }
+
+        let recorder = take_stopped_recorder(&mut state).unwrap();
+        assert_eq!(
+            recorder.hltas.lines,
+            [
+                complete("0.01", ""),
+                Line::FrameBulk(FrameBulk::with_frame_time("0.02".to_owned()))
+            ]
+        );
+    }
+
+    #[test]
+    fn locked_framerate() {
+        let frame_time = locked_frame_time("0.01").unwrap();
+        assert_eq!(format_frametime(frame_time), "0.01");
+        // Not the `float` value that ends up in `host_frametime`.
+        assert_ne!(frame_time, f64::from(0.01f32));
+
+        assert_eq!(locked_frame_time("0"), None);
+        assert_eq!(locked_frame_time("-1"), None);
+        assert_eq!(locked_frame_time(""), None);
+    }
+
+    #[test]
+    fn command_split() {
+        // A 10 ms frame split into two 5 ms commands, recorded as a single frame bulk.
+        let mut lines = vec![incomplete("")];
+        insert_split_comment(&mut lines, &[5, 5]);
+        assert_eq!(
+            fill_frame_bulks(&mut lines, &[0.01], &[0.], &[String::new()]),
+            Ok(true)
+        );
+        assert_eq!(
+            lines,
+            [
+                Line::Comment(" Command split: 5 + 5 ms".to_owned()),
+                Line::FrameBulk(FrameBulk::with_frame_time("0.01".to_owned())),
+            ]
+        );
+
+        // A single command, with or without a zero ms command before it, isn't a split.
+        let mut lines = vec![incomplete("")];
+        insert_split_comment(&mut lines, &[10]);
+        insert_split_comment(&mut lines, &[0, 10]);
+        assert_eq!(lines, [incomplete("")]);
+    }
+
+    #[test]
+    fn remainder_formatting() {
+        for remainder in [
+            0.1 + 0.2,
+            1e-10,
+            1e-17,
+            0.0009999999999999998,
+            f64::EPSILON,
+            0.000_100_000_000_000_01,
+            -0.25,
+        ] {
+            let formatted = remainder.to_string();
+            assert!(!formatted.contains('e'), "{}", formatted);
+            assert_eq!(formatted.parse::<f64>().unwrap(), remainder);
+        }
+    }
+
+    #[test]
+    fn disconnect_and_reconnect() {
+        let mut recorder = Recorder {
+            hltas: HLTAS {
+                lines: vec![complete("0.01", ""), incomplete("")],
+                ..HLTAS::default()
+            },
+            pending_frame_times: vec![0.01],
+            pending_console_commands: vec![String::new()],
+            last_client_state: Some(5),
+            ..Recorder::default()
+        };
+
+        // Loads don't count as a disconnect.
+        assert!(!is_disconnect(recorder.last_client_state, 3));
+        assert!(is_disconnect(recorder.last_client_state, 1));
+
+        mark_disconnect(&mut recorder);
+        assert_eq!(
+            recorder.hltas.lines,
+            [
+                complete("0.01", ""),
+                Line::Comment(" Disconnected".to_owned())
+            ]
+        );
+        assert!(recorder.pending_frame_times.is_empty());
+        assert!(recorder.pending_console_commands.is_empty());
+
+        // Only marked once, and the reconnect continues the recording as a fresh map start.
+        assert!(!is_disconnect(recorder.last_client_state, 1));
+        assert!(!is_disconnect(recorder.last_client_state, 5));
+        assert_ne!(recorder.last_client_state, Some(5));
+    }
+
+    #[test]
+    fn fill_frame_bulks_from_the_end() {
+        let mut lines = vec![
+            complete("0.01", "a"),
+            incomplete("b"),
+            Line::Comment(" comment".to_owned()),
+            incomplete(""),
+        ];
+
+        assert_eq!(
+            fill_frame_bulks(
+                &mut lines,
+                &[0.001, 0.002, 0.003],
+                &[0.1, 0.2, 0.3],
+                &["x".to_owned(), String::new(), "z".to_owned()],
+            ),
+            Ok(true)
+        );
+
+        assert_eq!(
+            lines,
+            [
+                complete("0.01", "a"),
+                complete("0.002", "b;_bxt_set_frametime_remainder 0.2"),
+                Line::Comment(" comment".to_owned()),
+                complete("0.003", "z;_bxt_set_frametime_remainder 0.3"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_frame_bulks_collapses_command_split() {
+        // Jump pressed only on the first command of the frame.
+        let mut jumping = FrameBulk {
+            console_command: Some("b".to_owned()),
+            ..FrameBulk::with_frame_time(String::new())
+        };
+        jumping.action_keys.jump = true;
+
+        let mut lines = vec![
+            complete("0.01", "a"),
+            Line::FrameBulk(jumping),
+            Line::Comment(" comment".to_owned()),
+            incomplete(""),
+            incomplete("d"),
+        ];
+
+        assert_eq!(
+            fill_frame_bulks(&mut lines, &[0.015625], &[0.5], &["x".to_owned()]),
+            Ok(true)
+        );
+
+        let mut expected = FrameBulk {
+            console_command: Some("x;b;d;_bxt_set_frametime_remainder 0.5".to_owned()),
+            ..FrameBulk::with_frame_time("0.015625".to_owned())
+        };
+        expected.action_keys.jump = true;
+        assert_eq!(
+            lines,
+            [
+                complete("0.01", "a"),
+                Line::Comment(" comment".to_owned()),
+                Line::FrameBulk(expected),
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_frame_bulks_omits_zero_remainder() {
+        let mut lines = vec![incomplete(""), incomplete("a"), incomplete("b")];
+
+        assert_eq!(
+            fill_frame_bulks(
+                &mut lines,
+                &[0.01, 0.01, 0.01],
+                &[0., 0., 0.25],
+                &["x".to_owned(), String::new(), String::new()],
+            ),
+            Ok(true)
+        );
+
+        assert_eq!(
+            lines,
+            [
+                complete("0.01", "x"),
+                complete("0.01", "a"),
+                complete("0.01", "b;_bxt_set_frametime_remainder 0.25"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_frame_bulks_without_console_command() {
+        let mut lines = vec![incomplete("")];
+
+        assert_eq!(
+            fill_frame_bulks(&mut lines, &[0.01], &[0.], &[String::new()]),
+            Ok(true)
+        );
+
+        assert_eq!(
+            lines,
+            [Line::FrameBulk(FrameBulk::with_frame_time(
+                "0.01".to_owned()
+            ))]
+        );
+    }
+
+    #[test]
+    fn fill_frame_bulks_with_remainder_before_first_command() {
+        // `on_cl_move()` buffered a remainder before the first command was recorded.
+        let mut lines = vec![incomplete("")];
+
+        assert_eq!(
+            fill_frame_bulks(&mut lines, &[0.01], &[0.25], &[String::new()],),
+            Ok(true)
+        );
+
+        assert_eq!(
+            lines,
+            [complete("0.01", "_bxt_set_frametime_remainder 0.25")]
+        );
+    }
+
+    #[test]
+    fn fill_frame_bulks_with_missing_remainders() {
+        let mut lines = vec![incomplete("a"), incomplete("")];
+
+        assert_eq!(
+            fill_frame_bulks(
+                &mut lines,
+                &[0.01, 0.02],
+                &[0.5],
+                &[String::new(), String::new()],
+            ),
+            Ok(true)
+        );
+
+        assert_eq!(
+            lines,
+            [
+                complete("0.01", "a"),
+                complete("0.02", "_bxt_set_frametime_remainder 0.5"),
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_frame_bulks_with_missing_console_commands() {
+        let mut lines = vec![incomplete("a"), incomplete("b")];
+
+        assert_eq!(
+            fill_frame_bulks(&mut lines, &[0.01, 0.02], &[0., 0.], &[String::new()]),
+            Err(MissingConsoleCommands)
+        );
+
+        // Nothing was filled in.
+        assert_eq!(lines, [incomplete("a"), incomplete("b")]);
+    }
+
+    #[test]
+    fn zero_ms_frame_bulks() {
+        let mut hltas = HLTAS {
+            properties: Properties::default(),
+            lines: vec![complete("0.01", "a"), complete("0.0000001", "b")],
+        };
+
+        mark_zero_ms_frame_bulk(&mut hltas);
+        hltas.lines.push(complete("0.01", "c"));
+        hltas.lines.push(complete("0.0000002", "d"));
+        hltas.lines.push(Line::Comment(" comment".to_owned()));
+        mark_zero_ms_frame_bulk(&mut hltas);
+
+        // The property keeps the first 0 ms frame time.
+        assert_eq!(hltas.properties.frametime_0ms.as_deref(), Some("0.0000001"));
+        assert_eq!(
+            hltas.lines,
+            [
+                complete("0.01", "a"),
+                complete("0", "b"),
+                complete("0.01", "c"),
+                complete("0", "d"),
+                Line::Comment(" comment".to_owned()),
+            ]
+        );
+    }
+
+    #[test]
+    fn fill_frame_bulks_without_frame_bulks() {
+        let mut lines = vec![complete("0.01", "a")];
+        assert_eq!(
+            fill_frame_bulks(&mut lines, &[0.01], &[0.], &[String::new()]),
+            Ok(false)
+        );
+        assert_eq!(lines, [complete("0.01", "a")]);
+    }
+}
@@ -0,0 +1,3506 @@
+//! TAS recording.
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt::Write;
+use std::fs::{self, File};
+use std::num::NonZeroU32;
+use std::os::raw::{c_char, c_int};
+use std::path::{Path, PathBuf};
+use std::ptr::NonNull;
+use std::str::FromStr;
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+use std::{io, mem};
+
+use hltas::types::{
+    ActionKeys, AutoMovement, FrameBulk, LeaveGroundAction, LeaveGroundActionSpeed,
+    LeaveGroundActionType, Line, MovementKeys, Properties, Times,
+};
+use hltas::HLTAS;
+
+use super::{Module, MODULES};
+use crate::ffi::buttons::Buttons;
+use crate::ffi::edict;
+use crate::ffi::usercmd::usercmd_s;
+use crate::hooks::engine::{self, con_print, RngState};
+use crate::modules::commands::{self, Command};
+use crate::modules::cvars::{self, CVar};
+use crate::utils::*;
+
+mod autostrafe;
+mod binary;
+mod console;
+mod frames;
+mod sink;
+mod transforms;
+pub use console::{recorded_script, start_recording, stop_recording};
+use frames::{is_recorded_command, split_commands};
+pub use frames::{
+    on_cbuf_addtext, on_cl_move, on_cmd_start, on_demo_update_client_data, on_key_event_end,
+    on_key_event_start, on_sv_frame_end, on_sv_frame_start,
+};
+use sink::{BinarySink, OriginsSink, PlayerPosition, RecordingSink, UsercmdsSink};
+use transforms::{drop_incomplete_frame_bulks, transform_lines};
+
+pub struct TasRecording;
+impl Module for TasRecording {
+    fn name(&self) -> &'static str {
+        "TAS recording"
+    }
+
+    fn description(&self) -> &'static str {
+        "\
+Recording of real-time gameplay into a HLTAS script.
+
+This is not perfect and needs a Bunnymod XT patch to set `host_frametime` directly instead of \
+going through the `host_framerate` console variable (thus missing precision due to the \
+`f64`->`f32`->`f64` roundtrip) for better sync. Nevertheless, it was very useful for diagnosing \
+and fixing a number of TAS determinism issues.
+
+Demo playback can be recorded too, although demos only store the view angles and the frame times, \
+so the movement, the buttons and the console commands are missing from such recordings."
+    }
+
+    fn commands(&self) -> &'static [&'static Command] {
+        console::COMMANDS
+    }
+
+    fn cvars(&self) -> &'static [&'static CVar] {
+        static CVARS: &[&CVar] = &[
+            &BXT_TAS_RECORDING_AUTOSTRAFE,
+            &BXT_TAS_RECORDING_WRITE_DEMO,
+            &BXT_TAS_RECORDING_HLSTRAFE_VERSION,
+            &BXT_TAS_RECORDING_ANGLE_ANCHOR_THRESHOLD,
+            &BXT_TAS_RECORDING_TELEPORT_THRESHOLD,
+            &BXT_TAS_RECORDING_DETECT_LGAGST,
+            &BXT_TAS_RECORDING_DETECT_DUCKTAP,
+            &BXT_TAS_RECORDING_DETECT_CHANGE,
+            &BXT_TAS_RECORDING_OMIT_REPEAT_YAW,
+            &BXT_TAS_RECORDING_SPLIT_ON_MAP,
+            &BXT_TAS_RECORDING_TRACK_CVARS,
+            &BXT_TAS_RECORDING_SNAPSHOT_CVARS,
+            &BXT_TAS_RECORDING_EXTRA_BUTTONS,
+            &BXT_TAS_RECORDING_MAX_FRAMES,
+            &BXT_TAS_RECORDING_DIR,
+            &BXT_TAS_RECORDING_AUTOSAVE_INTERVAL,
+            &BXT_TAS_RECORDING_ANGLE_DECIMALS,
+            &BXT_TAS_RECORDING_RELATIVE_YAW,
+            &BXT_TAS_RECORDING_CRLF,
+            &BXT_TAS_RECORDING_LOG_ORIGINS,
+            &BXT_TAS_RECORDING_EMIT_SPEED_COMMANDS,
+            &BXT_TAS_RECORDING_VERBOSE,
+            &BXT_TAS_RECORDING_FORMAT,
+            &BXT_TAS_RECORDING_FIRE_COMMENTS,
+            &BXT_TAS_RECORDING_WATER_COMMENTS,
+            &BXT_TAS_RECORDING_DUMP_USERCMDS,
+            &BXT_TAS_RECORDING_HUD,
+        ];
+        CVARS
+    }
+
+    fn required_pointers(&self) -> &'static [&'static dyn PointerTrait] {
+        static POINTERS: &[&dyn PointerTrait] = &[
+            &engine::CL_Move,
+            &engine::cls,
+            &engine::frametime_remainder,
+            &engine::host_frametime,
+            &engine::SV_Frame,
+            &engine::sv,
+            // For the player state, like being on the ground.
+            &engine::svs,
+        ];
+        POINTERS
+    }
+
+    fn is_enabled(&self, marker: MainThreadMarker) -> bool {
+        commands::Commands.is_enabled(marker)
+            && cvars::CVars.is_enabled(marker)
+            && self
+                .required_pointers()
+                .iter()
+                .all(|pointer| pointer.is_set(marker))
+            // Without it, pauses would be recorded as regular frames.
+            && engine::sv_paused_offset.get(marker).is_some()
+    }
+}
+
+static BXT_TAS_RECORDING_AUTOSTRAFE: CVar = CVar::new(
+    b"bxt_tas_recording_autostrafe\0",
+    b"0\0",
+    "\
+Set to `1` to record air strafing as autostrafing frame bulks, that is, to start recordings in the \
+`strafe` mode of `bxt_tas_recording_mode`.
+
+When the angle between the velocity and the movement direction matches optimal strafing, the frame \
+bulk uses max-accel strafing to the left or to the right instead of the recorded yaw. Other frames \
+are recorded with the yaw as usual.",
+);
+
+static BXT_TAS_RECORDING_WRITE_DEMO: CVar = CVar::new(
+    b"_bxt_tas_recording_write_demo\0",
+    b"0\0",
+    "\
+Set to `1` to write the `demo` property into the recorded script, named after the script file, so \
+that playing back the script records a demo.",
+);
+
+static BXT_TAS_RECORDING_HLSTRAFE_VERSION: CVar = CVar::new(
+    b"_bxt_tas_recording_hlstrafe_version\0",
+    b"4\0",
+    "\
+The `hlstrafe_version` property to write into the recorded script. Set to `0` to omit the property.",
+);
+
+static BXT_TAS_RECORDING_ANGLE_ANCHOR_THRESHOLD: CVar = CVar::new(
+    b"_bxt_tas_recording_angle_anchor_threshold\0",
+    b"90\0",
+    "\
+Change of the view angles between two frames, in degrees, above which the recording sets the \
+angles explicitly with `bxt_set_angles`, for example after a respawn. The angles are always set on \
+the first recorded frame. Set to `0` to only set them on the first frame.",
+);
+
+static BXT_TAS_RECORDING_TELEPORT_THRESHOLD: CVar = CVar::new(
+    b"_bxt_tas_recording_teleport_threshold\0",
+    b"64\0",
+    "\
+Distance in units that the player has to move between two frames beyond what their speed allows \
+for the recording to mark a teleport with a comment. Set to `0` to not mark teleports.",
+);
+
+static BXT_TAS_RECORDING_DETECT_LGAGST: CVar = CVar::new(
+    b"bxt_tas_recording_detect_lgagst\0",
+    b"0\0",
+    "\
+Set to `1` to record jumps off the ground above the leave-ground-at-greater-speed threshold as the \
+`lgagst` jump auto action instead of the jump key.",
+);
+
+static BXT_TAS_RECORDING_DETECT_DUCKTAP: CVar = CVar::new(
+    b"bxt_tas_recording_detect_ducktap\0",
+    b"0\0",
+    "\
+Set to `1` to record ducks pressed on the ground for a single frame as the `ducktap` auto action \
+instead of the duck key. Holding duck for longer, for example when crouch-walking, is recorded \
+with the duck key as usual.",
+);
+
+static BXT_TAS_RECORDING_OMIT_REPEAT_YAW: CVar = CVar::new(
+    b"_bxt_tas_recording_omit_repeat_yaw\0",
+    b"0\0",
+    "\
+Set to `1` to leave out the yaw of frame bulks where it's the same as in the previous frame bulk, \
+which keeps the yaw as is on replay. This makes the scripts smaller when the yaw rarely changes.",
+);
+
+static BXT_TAS_RECORDING_DETECT_CHANGE: CVar = CVar::new(
+    b"bxt_tas_recording_detect_change\0",
+    b"0\0",
+    "\
+Set to `1` to write smooth yaw sweeps, where the yaw changes by the same amount every frame, as a \
+single `change yaw` line instead of setting the yaw on every frame.",
+);
+
+static BXT_TAS_RECORDING_SPLIT_ON_MAP: CVar = CVar::new(
+    b"_bxt_tas_recording_split_on_map\0",
+    b"0\0",
+    "\
+Controls what happens when the map changes during a recording. `0` marks the map change with a \
+comment in the script. `1` writes the script recorded so far and continues recording into a new \
+script with `_2`, `_3` and so on appended to the file name.",
+);
+
+static BXT_TAS_RECORDING_TRACK_CVARS: CVar = CVar::new(
+    b"_bxt_tas_recording_track_cvars\0",
+    b"\0",
+    "\
+Semicolon-separated list of console variables to track during recordings, for example \
+`fov;sensitivity`. Whenever one of them changes, the recording sets it to the new value in the \
+console command of the frame bulk. The list is read when the recording starts.",
+);
+
+static BXT_TAS_RECORDING_SNAPSHOT_CVARS: CVar = CVar::new(
+    b"_bxt_tas_recording_snapshot_cvars\0",
+    b"cl_forwardspeed;cl_backspeed;cl_sidespeed;cl_upspeed;cl_movespeedkey;sv_maxspeed;\
+sv_accelerate;sv_airaccelerate;sv_friction;sv_stopspeed;sv_gravity\0",
+    "\
+Semicolon-separated list of console variables to set to their values at the start of the \
+recording, in the console command of the first frame bulk, so that the script replays under the \
+same settings. Variables that don't exist are skipped. Set to an empty string to not set any.",
+);
+
+static BXT_TAS_RECORDING_EXTRA_BUTTONS: CVar = CVar::new(
+    b"_bxt_tas_recording_extra_buttons\0",
+    b"\0",
+    "\
+Semicolon-separated list of additional buttons to record, for example `alt1;score=showscores`. \
+The names are the `IN_` button names without the prefix, optionally followed by `=` and the \
+command which presses the button if it isn't the lowercase name. Whenever one of them is pressed \
+or released, the recording issues `+command` or `-command` in the console command of the frame \
+bulk. The list is read when the recording starts.",
+);
+
+static BXT_TAS_RECORDING_MAX_FRAMES: CVar = CVar::new(
+    b"_bxt_tas_recording_max_frames\0",
+    b"0\0",
+    "\
+Maximum number of frames to record. When the recording reaches it, the recording is stopped and \
+written out. Set to `0` to record without a limit.",
+);
+
+static BXT_TAS_RECORDING_DIR: CVar = CVar::new(
+    b"_bxt_tas_recording_dir\0",
+    b"\0",
+    "\
+Directory that relative recording file names are resolved against. A leading `~` in the directory \
+or in the file name is expanded to the home directory. Leave empty to use the game directory.",
+);
+
+static BXT_TAS_RECORDING_AUTOSAVE_INTERVAL: CVar = CVar::new(
+    b"_bxt_tas_recording_autosave_interval\0",
+    b"0\0",
+    "\
+Interval in seconds of real time at which the script recorded so far is saved into a file with \
+`.autosave` appended to the name, so that a game crash doesn't lose the whole recording. The \
+autosave is removed when the recording stops. Set to `0` to disable autosaving.",
+);
+
+static BXT_TAS_RECORDING_ANGLE_DECIMALS: CVar = CVar::new(
+    b"_bxt_tas_recording_angle_decimals\0",
+    b"\0",
+    "\
+Number of decimals to round the recorded yaw and pitch to. Leave empty to keep the full precision \
+of the angles as they were sent to the server.",
+);
+
+static BXT_TAS_RECORDING_RELATIVE_YAW: CVar = CVar::new(
+    b"_bxt_tas_recording_relative_yaw\0",
+    b"0\0",
+    "\
+Set to `1` to write the yaws relative to the yaw of the first frame, which becomes the basis and is \
+written in a `Yaw basis` comment at the start of the script. A run can then be retargeted by only \
+changing the basis. Such scripts don't replay as is: the basis has to be added back to every yaw \
+first, otherwise the run is replayed rotated so that it starts looking at the yaw of 0.",
+);
+
+static BXT_TAS_RECORDING_CRLF: CVar = CVar::new(
+    b"_bxt_tas_recording_crlf\0",
+    b"0\0",
+    "\
+Set to `1` to write the recorded scripts with Windows (CRLF) line endings instead of LF.",
+);
+
+static BXT_TAS_RECORDING_EMIT_SPEED_COMMANDS: CVar = CVar::new(
+    b"_bxt_tas_recording_emit_speed_commands\0",
+    b"1\0",
+    "\
+Set to `0` to not record the speed variables (`cl_forwardspeed`, `cl_sidespeed` and so on) in the \
+frame bulk console commands, leaving only the movement keys. The replay then moves with whatever \
+speeds are set at that time.",
+);
+
+static BXT_TAS_RECORDING_VERBOSE: CVar = CVar::new(
+    b"_bxt_tas_recording_verbose\0",
+    b"0\0",
+    "\
+Set to `1` to log the recorded command and the resulting frame bulk on every frame to the bxt-rs \
+log. Useful for finding out why a frame was recorded the way it was.",
+);
+
+static BXT_TAS_RECORDING_FORMAT: CVar = CVar::new(
+    b"_bxt_tas_recording_format\0",
+    b"hltas\0",
+    "\
+Semicolon-separated formats of the new recordings: `hltas` to record a script, and `bin` to stream \
+the commands into a compact binary file, which is faster for very long recordings. With both, the \
+binary file gets `.bin` appended to the recording name. Binary recordings are turned into scripts \
+with `bxt_tas_recording_convert`. They only contain the buttons, the moves, the view angles and the \
+frame time of every frame.",
+);
+
+static BXT_TAS_RECORDING_FIRE_COMMENTS: CVar = CVar::new(
+    b"_bxt_tas_recording_fire_comments\0",
+    b"0\0",
+    "\
+Set to `1` to add a `FIRE @ tick N` comment before every frame where `+attack` gets pressed, for \
+syncing effects to the weapon fire later.",
+);
+
+static BXT_TAS_RECORDING_WATER_COMMENTS: CVar = CVar::new(
+    b"_bxt_tas_recording_water_comments\0",
+    b"0\0",
+    "\
+Set to `1` to add a `Water level` comment before every frame where the player's water level \
+changes, going between dry, feet, waist and eyes deep. The movement works differently in water, \
+so these mark where the strafing changes.",
+);
+
+static BXT_TAS_RECORDING_DUMP_USERCMDS: CVar = CVar::new(
+    b"_bxt_tas_recording_dump_usercmds\0",
+    b"0\0",
+    "\
+Set to `1` to write every field of every command run by the server into a CSV file with \
+`.usercmds` appended to the recording name, including the fields that the script doesn't use, like \
+the light level. This is purely diagnostic, for comparing the commands of two runs.",
+);
+
+static BXT_TAS_RECORDING_HUD: CVar = CVar::new(
+    b"_bxt_tas_recording_hud\0",
+    b"1\0",
+    "\
+Set to `0` to hide the on-screen indicator with the file name and the frame count that is shown \
+while recording. The indicator is only available on Linux.",
+);
+
+static BXT_TAS_RECORDING_LOG_ORIGINS: CVar = CVar::new(
+    b"_bxt_tas_recording_log_origins\0",
+    b"0\0",
+    "\
+Set to `1` to write the player origin at the start of every recorded physics frame into a file \
+with `.origins` appended to the recording name, along with the view angles. Each line is \
+`tick x y z pitch yaw roll`, which can be compared against a replay to find the first tick where it goes off-course.",
+);
+
+enum State {
+    Idle,
+    Recording(Recorder),
+}
+
+#[derive(Default)]
+struct Recorder {
+    hltas: HLTAS,
+    filename: PathBuf,
+    pending_frame_times: Vec<f64>,
+    /// `msec` of every command run in each of the pending frames, aligned to the frame times.
+    pending_msecs: Vec<Vec<u8>>,
+    /// Distinct frame times of the whole recording along with the number of frames with each.
+    frame_time_counts: Vec<(String, u64)>,
+    pending_remainders: Vec<f64>,
+    pending_bound_commands: Vec<String>,
+    pending_console_commands: Vec<String>,
+    keys: Keys,
+    /// Whether `+moveup` is held according to the bound commands.
+    ///
+    /// Unlike the other movement keys, up and down don't have button bits in the command.
+    up_held: bool,
+    /// Whether `+movedown` is held according to the bound commands.
+    down_held: bool,
+    last_cmd_was_zero_ms: bool,
+    /// The pause that the game is currently in.
+    pause: Option<Pause>,
+    /// Whether the recording itself is paused.
+    paused: bool,
+    /// Shared seed of the last recorded command.
+    last_shared_seed: Option<u32>,
+    /// Non-shared RNG state at the end of the last physics frame.
+    predicted_rng_state: Option<RngState>,
+    /// View angles of the last recorded command.
+    last_viewangles: Option<[f32; 3]>,
+    /// Whether jump was held in the last recorded command.
+    last_cmd_jump: bool,
+    /// Whether `+attack` was held in the last command, for the fire comments.
+    last_cmd_attack: bool,
+    duck_tracker: DuckTracker,
+    /// Name of the map that the recording is on.
+    current_map: Option<String>,
+    /// Number of times the recording was split into a new script.
+    split_count: u32,
+    mode: Mode,
+    /// Names of the tracked console variables along with their last recorded values.
+    tracked_cvars: Vec<(String, Option<String>)>,
+    /// Whether the `clean` mode suspended the movement assists of the modules.
+    assists_suspended: bool,
+    /// Movement assist variables turned off by the `clean` mode along with the values to restore.
+    suspended_cvars: Vec<(String, String)>,
+    /// Commands setting the console variables to their values at the start, for the first frame.
+    snapshot_commands: Vec<String>,
+    /// Additional buttons to record along with the commands pressing them.
+    extra_buttons: Vec<(Buttons, String)>,
+    /// Buttons of the last command, for the extra buttons.
+    last_buttons: u16,
+    /// Number of frame bulks recorded so far, across all parts.
+    frame_count: u64,
+    /// Client state at the last physics frame.
+    last_client_state: Option<c_int>,
+    /// Player origin at the start of the last frame, for detecting teleports.
+    last_origin: Option<[f32; 3]>,
+    /// Water level of the player at the start of the last frame, for the water level comments.
+    last_water_level: Option<c_int>,
+    /// Whether the player was on the ground at the start of the previous physics frame.
+    was_onground: bool,
+    /// Whether the player is on the ground at the start of the current physics frame.
+    ///
+    /// The detectors of the auto actions use this rather than reading the player state on their
+    /// own, so that they all agree on the ground transitions.
+    is_onground: bool,
+    /// Whether the client became active since the last recorded command, as on a fresh map start.
+    map_started: bool,
+    /// When the recording was last autosaved.
+    last_autosave: Option<Instant>,
+    /// Number of physics frames since the recording started.
+    tick: u64,
+    /// Recorded frame to stop the recording at, compared against `tick`.
+    stop_at: Option<u64>,
+    /// Whether `bxt_tas_recording_stop` was issued and the recording should stop at the end of the
+    /// frame.
+    stop_requested: bool,
+    /// Outputs of the recording besides the script, like the binary file or the origins sidecar.
+    sinks: Vec<Box<dyn RecordingSink>>,
+    /// Whether the recording only goes into the sinks, without building the script.
+    sinks_only: bool,
+    /// Command record of the current frame for the sinks.
+    pending_record: Option<binary::Record>,
+}
+
+/// How the movement is encoded in the frame bulks.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    /// The yaw is recorded as is.
+    #[default]
+    Raw,
+    /// Air strafing is recorded as autostrafing.
+    Strafe,
+}
+
+impl FromStr for Mode {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "raw" => Ok(Self::Raw),
+            "strafe" => Ok(Self::Strafe),
+            _ => Err(()),
+        }
+    }
+}
+
+/// Tracks the duck key across the recorded commands to detect ducktaps.
+#[derive(Debug, Default, Clone, Copy)]
+struct DuckTracker {
+    /// Whether duck was held in the command before the last one.
+    held_before_last: bool,
+    /// Whether duck was held in the last command.
+    held_last: bool,
+}
+
+impl DuckTracker {
+    /// Records the next command and returns whether the last command was a ducktap.
+    ///
+    /// A ducktap is a duck pressed on the ground for exactly one command. `on_ground_last` is
+    /// whether the player was on the ground at the start of the last command's frame.
+    fn update(&mut self, duck: bool, on_ground_last: bool) -> bool {
+        let is_ducktap = !self.held_before_last && self.held_last && on_ground_last && !duck;
+
+        self.held_before_last = self.held_last;
+        self.held_last = duck;
+
+        is_ducktap
+    }
+}
+
+static STATE: MainThreadRefCell<State> = MainThreadRefCell::new(State::Idle);
+
+/// Demos being converted by `bxt_tas_recording_from_demos`.
+struct Batch {
+    /// Demos left to convert, in reverse order.
+    demos: Vec<PathBuf>,
+    /// Path to the game directory, which the demo paths are relative to.
+    game_dir: PathBuf,
+    succeeded: u32,
+    failed: u32,
+}
+
+static BATCH: MainThreadRefCell<Option<Batch>> = MainThreadRefCell::new(None);
+
+/// Replay accuracy self-test in progress.
+struct SelfTest {
+    /// Player positions logged during the recording, by tick.
+    origins: Vec<(u64, PlayerPosition)>,
+    /// Number of physics frames since the replay started.
+    tick: u64,
+}
+
+static SELFTEST: MainThreadRefCell<Option<SelfTest>> = MainThreadRefCell::new(None);
+
+/// Message of the last error of the recording.
+static LAST_ERROR: MainThreadRefCell<Option<String>> = MainThreadRefCell::new(None);
+
+/// Maximum distance between the recorded and the replayed origin on the same tick.
+const SELFTEST_THRESHOLD: f32 = 0.01;
+
+/// Maximum difference in degrees between the recorded and the replayed view angles.
+const SELFTEST_ANGLE_THRESHOLD: f32 = 0.01;
+
+#[derive(Debug, Clone, Copy)]
+struct Pause {
+    /// Client state at the start of the pause.
+    client_state: c_int,
+    /// Whether the client state changed during the pause, which means that this is a load.
+    is_load: bool,
+    /// Number of commands which arrived during the pause.
+    command_count: u32,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Key {
+    state: u8,
+}
+
+impl Key {
+    fn down(&mut self) {
+        self.state |= 1 + 2;
+    }
+
+    fn up(&mut self) {
+        self.state = 4;
+    }
+
+    fn is_down(self) -> bool {
+        (self.state & 1) != 0
+    }
+
+    fn update(&mut self, down: bool) {
+        if down && !self.is_down() {
+            self.down();
+        } else if !down && self.is_down() {
+            self.up();
+        }
+    }
+
+    fn clear_impulses(&mut self) {
+        self.state &= !(2 + 4);
+    }
+
+    fn multiplier(self) -> f64 {
+        if (self.state & 2) != 0 {
+            if (self.state & 4) != 0 {
+                0.75
+            } else {
+                0.5
+            }
+        } else {
+            1.
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+struct Keys {
+    forward: Key,
+    back: Key,
+    left: Key,
+    right: Key,
+    up: Key,
+    down: Key,
+    /// Whether `+speed` is held.
+    walking: bool,
+}
+
+impl Keys {
+    fn clear_impulses(&mut self) {
+        self.forward.clear_impulses();
+        self.back.clear_impulses();
+        self.left.clear_impulses();
+        self.right.clear_impulses();
+        self.up.clear_impulses();
+        self.down.clear_impulses();
+    }
+}
+
+/// Console commands reproducing the moves of a command.
+#[derive(Debug, Default)]
+struct Moves {
+    commands: Vec<String>,
+    /// Whether the moves couldn't be reproduced exactly.
+    approximated: bool,
+}
+
+/// Updates the key states from the held movement keys and resolves them against the moves.
+///
+/// `movement_keys` should contain the keys held during the command; it's updated to the keys that
+/// the frame bulk should have. `walk` is the multiplier that `+speed` applies to the key moves, or
+/// `1` when not walking. Returns the console commands setting the movement speeds so that the
+/// moves of `cmd` are reproduced.
+fn record_movement(
+    keys: &mut Keys,
+    cmd: &usercmd_s,
+    movement_keys: &mut MovementKeys,
+    walk: f64,
+) -> Moves {
+    // Analog input (for example, from a joystick) moves without any keys. It's replayed with the
+    // key in the direction of the move, so the key goes through its press impulse and the walk
+    // multiplier like any other.
+    hold_analog_key(
+        cmd.forwardmove,
+        &mut movement_keys.forward,
+        &mut movement_keys.back,
+    );
+    hold_analog_key(
+        cmd.sidemove,
+        &mut movement_keys.right,
+        &mut movement_keys.left,
+    );
+    hold_analog_key(cmd.upmove, &mut movement_keys.up, &mut movement_keys.down);
+
+    keys.forward.update(movement_keys.forward);
+    keys.back.update(movement_keys.back);
+    keys.left.update(movement_keys.left);
+    keys.right.update(movement_keys.right);
+    keys.up.update(movement_keys.up);
+    keys.down.update(movement_keys.down);
+
+    let mut moves = Moves::default();
+
+    resolve_axis(
+        cmd.forwardmove,
+        (
+            &mut movement_keys.forward,
+            &mut keys.forward,
+            "cl_forwardspeed",
+        ),
+        (&mut movement_keys.back, &mut keys.back, "cl_backspeed"),
+        walk,
+        &mut moves,
+    );
+    resolve_axis(
+        cmd.sidemove,
+        (&mut movement_keys.right, &mut keys.right, "cl_sidespeed"),
+        (&mut movement_keys.left, &mut keys.left, "cl_sidespeed"),
+        walk,
+        &mut moves,
+    );
+    resolve_axis(
+        cmd.upmove,
+        (&mut movement_keys.up, &mut keys.up, "cl_upspeed"),
+        (&mut movement_keys.down, &mut keys.down, "cl_upspeed"),
+        walk,
+        &mut moves,
+    );
+
+    moves
+}
+
+/// Holds the key in the direction of the move value if neither of the opposing keys is held.
+fn hold_analog_key(value: f32, positive: &mut bool, negative: &mut bool) {
+    if value == 0. || *positive || *negative {
+        return;
+    }
+
+    if value > 0. {
+        *positive = true;
+    } else {
+        *negative = true;
+    }
+}
+
+/// Resolves a pair of opposing movement keys against the resulting move value.
+///
+/// The positive key adds its speed variable to the move value, while the negative key subtracts its
+/// speed variable. The key moves are then scaled by `walk`. Adds the console commands setting the
+/// speed variables of the keys that end up held to `moves`.
+fn resolve_axis(
+    value: f32,
+    (positive, positive_key, positive_cvar): (&mut bool, &mut Key, &str),
+    (negative, negative_key, negative_cvar): (&mut bool, &mut Key, &str),
+    walk: f64,
+    moves: &mut Moves,
+) {
+    if value == 0. && !*positive && !*negative {
+        return;
+    }
+
+    let raw_value = value as f64;
+    // The value that the keys produced before the walk multiplier.
+    let value = raw_value / walk;
+
+    if *positive && *negative {
+        if positive_cvar != negative_cvar {
+            // With separate speed variables, one of them can produce the whole move value while
+            // the other one is zeroed out.
+            let (positive_speed, negative_speed) = if value >= 0. {
+                (key_speed(value, *positive_key, raw_value), 0.)
+            } else {
+                (0., key_speed(-value, *negative_key, -raw_value))
+            };
+
+            moves
+                .commands
+                .push(format!("{} {}", positive_cvar, positive_speed));
+            moves
+                .commands
+                .push(format!("{} {}", negative_cvar, negative_speed));
+            return;
+        }
+
+        // With a shared speed variable, the move value is the speed times the difference of the
+        // multipliers.
+        let difference = positive_key.multiplier() - negative_key.multiplier();
+        if difference != 0. {
+            moves
+                .commands
+                .push(format!("{} {}", positive_cvar, value / difference));
+            return;
+        }
+
+        if value == 0. {
+            // The keys cancel out with any speed.
+            return;
+        }
+
+        // Not possible to reproduce with both keys held, so drop one of them.
+        moves.approximated = true;
+        if value > 0. {
+            *negative = false;
+            negative_key.update(false);
+        } else {
+            *positive = false;
+            positive_key.update(false);
+        }
+    }
+
+    let command = if *positive {
+        format!(
+            "{} {}",
+            positive_cvar,
+            key_speed(value, *positive_key, raw_value)
+        )
+    } else {
+        format!(
+            "{} {}",
+            negative_cvar,
+            key_speed(-value, *negative_key, -raw_value)
+        )
+    };
+
+    moves.commands.push(command);
+}
+
+/// Returns the speed variable value which makes the key produce the move value.
+///
+/// Falls back to `raw_value` if the key multiplier gives a non-finite speed, which would otherwise
+/// end up in the script as `inf`.
+fn key_speed(value: f64, key: Key, raw_value: f64) -> f64 {
+    divide_by_multiplier(value, key.multiplier(), raw_value)
+}
+
+/// Divides the value by the multiplier, returning `fallback` if the result isn't finite.
+fn divide_by_multiplier(value: f64, multiplier: f64, fallback: f64) -> f64 {
+    let speed = value / multiplier;
+    if speed.is_finite() {
+        speed
+    } else {
+        warn!(
+            "key multiplier {} gave a non-finite speed for the move value {}, using {}",
+            multiplier, value, fallback
+        );
+        fallback
+    }
+}
+
+/// Returns the shared seed to record before a command with the given seed, if any.
+///
+/// The shared seed normally increases by one with every command. Loads can vary in length, and the
+/// seed can jump for other reasons too (for example, when the client reconnects), so such changes
+/// need to be recorded.
+fn shared_seed_change(last_seed: Option<u32>, seed: u32, was_loading: bool) -> Option<u32> {
+    let last_seed = last_seed?;
+
+    if was_loading || seed != last_seed.wrapping_add(1) {
+        Some(seed.wrapping_sub(last_seed))
+    } else {
+        None
+    }
+}
+
+/// Returns the seed which the non-shared RNG was seeded with, if it can be told.
+///
+/// Right after seeding and before generating any numbers, the RNG keeps the negative seed in
+/// `idum` and has `iy` unset. Once numbers are generated, the seed is lost.
+fn reset_seed(state: RngState) -> Option<i64> {
+    if state.iy == 0 && state.idum < 0 {
+        Some(i64::from(state.idum))
+    } else {
+        None
+    }
+}
+
+/// Returns the command setting the non-shared RNG state before a command, if needed.
+///
+/// The non-shared RNG is also advanced outside of the physics frames (for example, by client-side
+/// effects), so its state is set whenever it doesn't match the state at the end of the previous
+/// physics frame. Without a prediction (on further commands within the same physics frame), the
+/// state is assumed to match.
+fn rng_set_command(
+    is_first: bool,
+    predicted: Option<RngState>,
+    current: RngState,
+) -> Option<String> {
+    if is_first || matches!(predicted, Some(predicted) if predicted != current) {
+        Some(format!("_bxt_rng_set \"{}\"", current))
+    } else {
+        None
+    }
+}
+
+/// Default minimum speed for leaving the ground at greater speed in Bunnymod XT.
+const LGAGST_MIN_SPEED: f32 = 30.;
+
+/// Movement parameters for comparing the ground and the air acceleration.
+#[derive(Debug, Clone, Copy)]
+struct LgagstParameters {
+    frame_time: f32,
+    max_speed: f32,
+    accelerate: f32,
+    air_accelerate: f32,
+    friction: f32,
+    stop_speed: f32,
+    ent_friction: f32,
+}
+
+/// Returns whether a command holding jump is a jump off the ground at greater speed.
+///
+/// The jump has to be pressed in this command, as holding it doesn't jump again, the speed must be
+/// above the `lgagst` threshold, and the jump has to be on a frame that `lgagst` would pick: where
+/// jumping gains more speed than strafing on the ground for another frame. Other jumps should be
+/// recorded with the jump key.
+fn is_lgagst_jump(
+    parameters: LgagstParameters,
+    on_ground: bool,
+    jump_was_held: bool,
+    speed: f32,
+) -> bool {
+    on_ground && !jump_was_held && speed >= LGAGST_MIN_SPEED && jump_is_optimal(parameters, speed)
+}
+
+/// Returns whether jumping gains more speed than staying on the ground for another frame.
+///
+/// A jump leaves the ground before the friction is applied, so the jump frame is strafed in the
+/// air, while staying on the ground loses speed to friction before strafing at the ground
+/// acceleration.
+fn jump_is_optimal(parameters: LgagstParameters, speed: f32) -> bool {
+    let LgagstParameters {
+        frame_time,
+        max_speed,
+        accelerate,
+        air_accelerate,
+        friction,
+        stop_speed,
+        ent_friction,
+    } = parameters;
+
+    let control = speed.max(stop_speed);
+    let after_friction = (speed - control * friction * ent_friction * frame_time).max(0.);
+    let ground = max_accel_speed(
+        after_friction,
+        max_speed,
+        accelerate * max_speed * ent_friction * frame_time,
+    );
+
+    // The air wish speed is capped at 30, but the acceleration uses the full one.
+    let air = max_accel_speed(
+        speed,
+        max_speed.min(30.),
+        air_accelerate * max_speed * ent_friction * frame_time,
+    );
+
+    air >= ground
+}
+
+/// Returns the speed after a frame of strafing at the angle giving the maximum acceleration.
+fn max_accel_speed(speed: f32, wish_speed: f32, accel_speed: f32) -> f32 {
+    if accel_speed <= 0. {
+        return speed;
+    }
+
+    if wish_speed < accel_speed {
+        // Strafing perpendicular to the velocity adds the whole wish speed.
+        speed.hypot(wish_speed)
+    } else if speed <= wish_speed - accel_speed {
+        speed + accel_speed
+    } else {
+        (speed * speed - accel_speed * accel_speed + 2. * accel_speed * wish_speed).sqrt()
+    }
+}
+
+/// Replaces the held jump key of the frame bulk with the autojump action.
+///
+/// A leave ground action that's already there, like a detected LGAGST, is kept.
+fn apply_autojump(frame_bulk: &mut FrameBulk) {
+    if frame_bulk.auto_actions.leave_ground_action.is_some() {
+        return;
+    }
+
+    frame_bulk.action_keys.jump = false;
+    frame_bulk.auto_actions.leave_ground_action = Some(LeaveGroundAction {
+        speed: LeaveGroundActionSpeed::Any,
+        times: Times::UnlimitedWithinFrameBulk,
+        type_: LeaveGroundActionType::Jump,
+    });
+}
+
+/// Returns whether duck and jump are both pressed in this command, that is, a crouch-jump.
+///
+/// Both arguments are `(duck, jump)` pairs for this and the last command.
+fn is_crouch_jump((duck, jump): (bool, bool), (last_duck, last_jump): (bool, bool)) -> bool {
+    duck && jump && !last_duck && !last_jump
+}
+
+/// Returns the comment marking the tick where `+attack` got pressed, if it did in this command.
+fn fire_comment(attack: bool, last_attack: bool, tick: u64) -> Option<Line> {
+    if attack && !last_attack {
+        Some(Line::Comment(format!(" FIRE @ tick {}", tick)))
+    } else {
+        None
+    }
+}
+
+/// Replaces the duck key in the last frame bulk with the `ducktap` auto action.
+///
+/// Does nothing if the frame bulk already has a leave ground action or jumps, as in a crouch-jump.
+fn convert_to_ducktap(lines: &mut [Line]) {
+    let frame_bulk = lines.iter_mut().rev().find_map(|line| match line {
+        Line::FrameBulk(frame_bulk) => Some(frame_bulk),
+        _ => None,
+    });
+
+    if let Some(frame_bulk) = frame_bulk {
+        if frame_bulk.action_keys.duck
+            && !frame_bulk.action_keys.jump
+            && frame_bulk.auto_actions.leave_ground_action.is_none()
+        {
+            frame_bulk.action_keys.duck = false;
+            frame_bulk.auto_actions.leave_ground_action = Some(LeaveGroundAction {
+                speed: LeaveGroundActionSpeed::Any,
+                times: Times::Limited(NonZeroU32::new(1).unwrap()),
+                type_: LeaveGroundActionType::DuckTap { zero_ms: false },
+            });
+        }
+    }
+}
+
+/// Returns whether the pitch or the yaw changed by more than `threshold` degrees.
+fn angles_jumped(last: [f32; 3], current: [f32; 3], threshold: f32) -> bool {
+    let difference = |a: f32, b: f32| ((b - a + 180.).rem_euclid(360.) - 180.).abs();
+
+    difference(last[0], current[0]) > threshold || difference(last[1], current[1]) > threshold
+}
+
+/// Formats the frame time for the frame bulk.
+///
+/// Uses six decimal places like the engine does, with the trailing zeros trimmed, so that
+/// floating-point noise like `0.010000000000000002` doesn't end up in the script.
+fn format_frametime(frame_time: f64) -> String {
+    let mut formatted = format!("{:.6}", frame_time);
+
+    let trimmed_len = formatted.trim_end_matches('0').trim_end_matches('.').len();
+    formatted.truncate(trimmed_len);
+
+    formatted
+}
+
+/// Formats the Unix timestamp as a UTC date and time.
+fn format_utc(timestamp: u64) -> String {
+    let days = timestamp / 86400;
+    let seconds = timestamp % 86400;
+
+    // Convert days since the epoch into a civil date. The algorithm is from
+    // http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+    let z = days + 719468;
+    let era = z / 146097;
+    let doe = z % 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// Returns the commands restoring the variables turned off by the `clean` mode.
+fn restore_commands(suspended: &[(String, String)]) -> String {
+    let mut commands = String::new();
+    for (name, value) in suspended {
+        writeln!(commands, "{} \"{}\"", name, value)
+            .expect("writing to `String` should never error");
+    }
+    commands
+}
+
+/// Restores the movement assists turned off by the `clean` mode.
+fn restore_assists(
+    marker: MainThreadMarker,
+    assists_suspended: bool,
+    suspended_cvars: &[(String, String)],
+) {
+    if assists_suspended {
+        for module in MODULES {
+            if module.is_enabled(marker) {
+                module.restore_assists(marker);
+            }
+        }
+    }
+
+    if !suspended_cvars.is_empty() {
+        engine::prepend_command(marker, &restore_commands(suspended_cvars));
+    }
+}
+
+fn start(marker: MainThreadMarker, filename: PathBuf, mut hltas: HLTAS) {
+    if !TasRecording.is_enabled(marker) {
+        return;
+    }
+
+    let mut state = STATE.borrow_mut(marker);
+    if matches!(*state, State::Idle) {
+        let mut formats = parse_cvar_list(&BXT_TAS_RECORDING_FORMAT.to_string(marker));
+        if formats.is_empty() {
+            formats.push("hltas".to_owned());
+        }
+        if let Some(format) = formats.iter().find(|f| !matches!(&f[..], "hltas" | "bin")) {
+            print_error(
+                marker,
+                &format!(
+                    "Error: unknown _bxt_tas_recording_format {}, expected hltas or bin\n",
+                    format
+                ),
+            );
+            return;
+        }
+        let sinks_only = !formats.iter().any(|format| format == "hltas");
+
+        let mut sinks: Vec<Box<dyn RecordingSink>> = Vec::new();
+        if formats.iter().any(|format| format == "bin") {
+            let path = if sinks_only {
+                filename.clone()
+            } else {
+                with_appended_extension(&filename, "bin")
+            };
+            match BinarySink::create(path) {
+                Ok(sink) => sinks.push(Box::new(sink)),
+                Err(err) => {
+                    print_error(
+                        marker,
+                        &format!("Error creating the binary recording: {}\n", err),
+                    );
+                    return;
+                }
+            }
+        }
+        if BXT_TAS_RECORDING_LOG_ORIGINS.as_bool(marker) {
+            let path = with_appended_extension(&filename, "origins");
+            match OriginsSink::create(path.clone()) {
+                Ok(sink) => sinks.push(Box::new(sink)),
+                Err(err) => print_error(
+                    marker,
+                    &format!(
+                        "Error creating {}, not logging the origins: {}\n",
+                        path.display(),
+                        err
+                    ),
+                ),
+            }
+        }
+
+        if BXT_TAS_RECORDING_DUMP_USERCMDS.as_bool(marker) {
+            let path = with_appended_extension(&filename, "usercmds");
+            match UsercmdsSink::create(path.clone()) {
+                Ok(sink) => sinks.push(Box::new(sink)),
+                Err(err) => print_error(
+                    marker,
+                    &format!(
+                        "Error creating {}, not dumping the commands: {}\n",
+                        path.display(),
+                        err
+                    ),
+                ),
+            }
+        }
+
+        // Keep the properties of an appended script.
+        let properties = &mut hltas.properties;
+        if properties.hlstrafe_version.is_none() {
+            properties.hlstrafe_version =
+                u32::try_from(BXT_TAS_RECORDING_HLSTRAFE_VERSION.as_u64(marker))
+                    .ok()
+                    .and_then(NonZeroU32::new);
+        }
+        if properties.demo.is_none() && BXT_TAS_RECORDING_WRITE_DEMO.as_bool(marker) {
+            properties.demo = filename
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .map(str::to_owned);
+        }
+
+        // SAFETY: we're in a console command callback, it's safe to call engine functions.
+        let build_number = engine::build_number
+            .get_opt(marker)
+            .map(|build_number| unsafe { build_number() });
+        hltas.lines.push(engine_comment(build_number));
+
+        // SAFETY: we're in a console command callback, it's safe to call engine functions.
+        if let Some(comment) = tickrate_comment(unsafe { cvar_string(marker, "sys_ticrate") }) {
+            hltas.lines.push(comment);
+        }
+
+        // SAFETY: we're in a console command callback, so the server data isn't being modified.
+        if let Some(comment) = unsafe { start_comment(marker) } {
+            hltas.lines.push(comment);
+        }
+        // SAFETY: same as above.
+        if let Some(comment) = unsafe { player_state_comment(marker) } {
+            hltas.lines.push(comment);
+        }
+
+        let mode = if BXT_TAS_RECORDING_AUTOSTRAFE.as_bool(marker) {
+            Mode::Strafe
+        } else {
+            Mode::Raw
+        };
+
+        let tracked_cvars = parse_cvar_list(&BXT_TAS_RECORDING_TRACK_CVARS.to_string(marker))
+            .into_iter()
+            .map(|name| (name, None))
+            .collect();
+
+        let snapshot_commands = cvar_snapshot_commands(
+            &parse_cvar_list(&BXT_TAS_RECORDING_SNAPSHOT_CVARS.to_string(marker)),
+            // SAFETY: we're in a console command callback, it's safe to call engine functions.
+            |name| unsafe { cvar_string(marker, name) },
+        );
+
+        let extra_buttons =
+            match parse_extra_buttons(&BXT_TAS_RECORDING_EXTRA_BUTTONS.to_string(marker)) {
+                Ok(extra_buttons) => extra_buttons,
+                Err(name) => {
+                    print_error(
+                        marker,
+                        &format!(
+                            "Error: unknown button {} in _bxt_tas_recording_extra_buttons\n",
+                            name
+                        ),
+                    );
+                    return;
+                }
+            };
+
+        *state = State::Recording(Recorder {
+            hltas,
+            filename,
+            mode,
+            tracked_cvars,
+            snapshot_commands,
+            extra_buttons,
+            sinks,
+            sinks_only,
+            ..Default::default()
+        });
+        con_print(marker, "Recording started\n");
+    } else {
+        con_print(marker, "Already recording\n");
+    }
+}
+
+/// Passes the current tick and the player origin to the sinks.
+///
+/// # Safety
+///
+/// The server data must not be modified while this function is running.
+unsafe fn record_tick(marker: MainThreadMarker, recorder: &mut Recorder) {
+    if recorder.sinks.is_empty() {
+        return;
+    }
+
+    let player = engine::player_edict(marker).map(|edict| player_position(edict));
+
+    let tick = recorder.tick;
+    feed_sinks(marker, &mut recorder.sinks, |sink| {
+        sink.on_tick(tick, player)
+    });
+}
+
+/// Returns the origin and the view angles of the player.
+///
+/// # Safety
+///
+/// The edict must be valid.
+unsafe fn player_position(edict: NonNull<edict::edict_s>) -> PlayerPosition {
+    // SAFETY: we're not calling any engine functions while the reference is alive.
+    let v = &edict.as_ref().v;
+    PlayerPosition {
+        origin: v.origin,
+        angles: v.v_angle,
+    }
+}
+
+/// Feeds data to every sink, dropping the sinks which fail.
+fn feed_sinks(
+    marker: MainThreadMarker,
+    sinks: &mut Vec<Box<dyn RecordingSink>>,
+    mut feed: impl FnMut(&mut dyn RecordingSink) -> io::Result<()>,
+) {
+    let mut i = 0;
+    while i < sinks.len() {
+        match feed(&mut *sinks[i]) {
+            Ok(()) => i += 1,
+            Err(err) => {
+                print_error(
+                    marker,
+                    &format!(
+                        "Error writing {}, stopping writing it: {}\n",
+                        sinks[i].path().display(),
+                        err
+                    ),
+                );
+                // Keeps what was written so far, unlike discarding it.
+                drop(sinks.remove(i));
+            }
+        }
+    }
+}
+
+/// Returns the console variable names from a semicolon-separated list.
+fn parse_cvar_list(list: &str) -> Vec<String> {
+    list.split(';')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .map(str::to_owned)
+        .collect()
+}
+
+/// Names of the buttons for `_bxt_tas_recording_extra_buttons`.
+const BUTTON_NAMES: &[(&str, Buttons)] = &[
+    ("attack", Buttons::IN_ATTACK),
+    ("jump", Buttons::IN_JUMP),
+    ("duck", Buttons::IN_DUCK),
+    ("forward", Buttons::IN_FORWARD),
+    ("back", Buttons::IN_BACK),
+    ("use", Buttons::IN_USE),
+    ("cancel", Buttons::IN_CANCEL),
+    ("left", Buttons::IN_LEFT),
+    ("right", Buttons::IN_RIGHT),
+    ("moveleft", Buttons::IN_MOVELEFT),
+    ("moveright", Buttons::IN_MOVERIGHT),
+    ("attack2", Buttons::IN_ATTACK2),
+    ("run", Buttons::IN_RUN),
+    ("reload", Buttons::IN_RELOAD),
+    ("alt1", Buttons::IN_ALT1),
+    ("score", Buttons::IN_SCORE),
+];
+
+/// Parses the `_bxt_tas_recording_extra_buttons` list into the buttons and their commands.
+///
+/// Returns the unknown button name on error.
+fn parse_extra_buttons(list: &str) -> Result<Vec<(Buttons, String)>, String> {
+    parse_cvar_list(list)
+        .into_iter()
+        .map(|entry| {
+            let (name, command) = match entry.split_once('=') {
+                Some((name, command)) => (name.trim(), Some(command.trim())),
+                None => (&entry[..], None),
+            };
+            let lowercase = name.to_ascii_lowercase();
+            let lowercase = lowercase.strip_prefix("in_").unwrap_or(&lowercase);
+
+            match BUTTON_NAMES.iter().find(|(name, _)| *name == lowercase) {
+                Some(&(_, button)) => Ok((button, command.unwrap_or(lowercase).to_owned())),
+                None => Err(name.to_owned()),
+            }
+        })
+        .collect()
+}
+
+/// Returns the commands pressing and releasing the extra buttons which changed since the last
+/// command.
+fn extra_button_commands(
+    extra_buttons: &[(Buttons, String)],
+    buttons: u16,
+    last: u16,
+) -> Vec<String> {
+    let buttons = Buttons::from_bits_truncate(buttons);
+    let last = Buttons::from_bits_truncate(last);
+
+    extra_buttons
+        .iter()
+        .filter_map(
+            |(button, command)| match (buttons.contains(*button), last.contains(*button)) {
+                (true, false) => Some(format!("+{}", command)),
+                (false, true) => Some(format!("-{}", command)),
+                _ => None,
+            },
+        )
+        .collect()
+}
+
+/// Returns the string value of the console variable, if it exists.
+unsafe fn cvar_string(marker: MainThreadMarker, name: &str) -> Option<String> {
+    let cvar = engine::find_cvar(marker, name)?;
+    Some(
+        CStr::from_ptr(cvar.as_ref().string)
+            .to_string_lossy()
+            .into_owned(),
+    )
+}
+
+/// Returns the commands setting the console variables to their current values.
+///
+/// Variables for which `value` returns `None` are skipped.
+fn cvar_snapshot_commands(names: &[String], value: impl Fn(&str) -> Option<String>) -> Vec<String> {
+    names
+        .iter()
+        .filter_map(|name| Some(format!("{} \"{}\"", name, value(name)?)))
+        .collect()
+}
+
+/// Returns the commands setting the tracked console variables which changed since the last call.
+unsafe fn tracked_cvar_commands(
+    marker: MainThreadMarker,
+    tracked_cvars: &mut [(String, Option<String>)],
+) -> Vec<String> {
+    let mut commands = Vec::new();
+
+    for (name, last_value) in tracked_cvars {
+        let value = match cvar_string(marker, name) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        if last_value.as_ref() != Some(&value) {
+            commands.push(format!("{} \"{}\"", name, value));
+            *last_value = Some(value);
+        }
+    }
+
+    commands
+}
+
+/// Returns the path of the recording file according to `_bxt_tas_recording_dir`.
+fn recording_path(marker: MainThreadMarker, filename: &Path) -> PathBuf {
+    let home = std::env::var_os("HOME")
+        .or_else(|| std::env::var_os("USERPROFILE"))
+        .map(PathBuf::from);
+
+    resolve_path(
+        &BXT_TAS_RECORDING_DIR.to_string(marker),
+        filename,
+        home.as_deref(),
+    )
+}
+
+/// Resolves the file name relative to the directory, expanding a leading `~` in either of them.
+///
+/// Absolute file names and file names starting with `~` are used as is. An empty directory leaves
+/// the file name relative to the current directory.
+fn resolve_path(dir: &str, filename: &Path, home: Option<&Path>) -> PathBuf {
+    let expand = |path: &Path| -> PathBuf {
+        match (path.strip_prefix("~"), home) {
+            (Ok(rest), Some(home)) => home.join(rest),
+            _ => path.to_owned(),
+        }
+    };
+
+    let filename = expand(filename);
+    if filename.is_absolute() || dir.is_empty() {
+        return filename;
+    }
+
+    expand(Path::new(dir)).join(filename)
+}
+
+/// Returns the comment with the map, the server time and the UTC time of the recording start.
+///
+/// The server time (`sv.time`) marks the position in the game instead of an engine tick count, which
+/// there's no pointer for. If the server isn't active, returns `None`.
+unsafe fn start_comment(marker: MainThreadMarker) -> Option<Line> {
+    let (map, time) = engine::server_map_and_time(marker)?;
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs());
+
+    Some(Line::Comment(format!(
+        " Recording started on {} at server time {:.3}, {}",
+        map,
+        time,
+        format_utc(now)
+    )))
+}
+
+/// Returns a comment with the engine build that the recording was made on.
+fn engine_comment(build_number: Option<c_int>) -> Line {
+    Line::Comment(match build_number {
+        Some(build_number) => format!(" engine: build {}", build_number),
+        None => " engine: unknown".to_owned(),
+    })
+}
+
+/// Returns a comment with the server tickrate from `sys_ticrate`, if the variable exists.
+///
+/// Dedicated servers run at this tickrate, which the frame times of a recording depend on.
+fn tickrate_comment(sys_ticrate: Option<String>) -> Option<Line> {
+    let tickrate = sys_ticrate?;
+    Some(Line::Comment(format!(" tickrate: {}", tickrate.trim())))
+}
+
+/// Returns a comment with the starting origin and velocity of the player, to compare recordings.
+///
+/// Returns `None` if there's no player yet, like when recording from the main menu.
+///
+/// # Safety
+///
+/// The server data must not be modified while this function is running.
+unsafe fn player_state_comment(marker: MainThreadMarker) -> Option<Line> {
+    // The player edict is only valid on an active server.
+    engine::server_map_and_time(marker)?;
+    let edict = engine::player_edict(marker)?;
+    // SAFETY: we're not calling any engine functions while the reference is alive.
+    let edict = edict.as_ref();
+
+    Some(Line::Comment(format_player_state(
+        edict.v.origin,
+        edict.v.velocity,
+    )))
+}
+
+/// Formats the comment text for [`player_state_comment()`].
+fn format_player_state(origin: [f32; 3], velocity: [f32; 3]) -> String {
+    format!(
+        " Starting origin: {:.3} {:.3} {:.3}, velocity: {:.3} {:.3} {:.3}",
+        origin[0], origin[1], origin[2], velocity[0], velocity[1], velocity[2]
+    )
+}
+
+/// Returns the file name of the given part of a split recording.
+///
+/// The first part uses the file name as is.
+fn part_filename(filename: &Path, part: u32) -> PathBuf {
+    if part <= 1 {
+        return filename.to_owned();
+    }
+
+    let stem = filename.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{}_{}", stem, part);
+    if let Some(extension) = filename.extension() {
+        name.push('.');
+        name.push_str(&extension.to_string_lossy());
+    }
+
+    filename.with_file_name(name)
+}
+
+/// Writes the script into the file, returning whether it succeeded.
+///
+/// The script is written into a temporary file first, which then replaces the output file, so a
+/// failed write never leaves a truncated output file behind.
+fn write_script(marker: MainThreadMarker, mut hltas: HLTAS, filename: &Path) -> bool {
+    transform_lines(marker, &mut hltas.lines);
+
+    let temp_filename = with_appended_extension(filename, "tmp");
+    let file = match File::create(&temp_filename) {
+        Ok(file) => file,
+        Err(err) => {
+            print_error(marker, &format!("Error opening the output file: {}\n", err));
+            return false;
+        }
+    };
+
+    if let Err(err) = serialize_script(marker, &hltas, file) {
+        print_error(
+            marker,
+            &format!("Error writing to the output file: {}\n", err),
+        );
+        let _ = fs::remove_file(&temp_filename);
+        return false;
+    }
+
+    if let Err(err) = fs::rename(&temp_filename, filename) {
+        print_error(
+            marker,
+            &format!("Error replacing the output file: {}\n", err),
+        );
+        let _ = fs::remove_file(&temp_filename);
+        return false;
+    }
+
+    true
+}
+
+/// Serializes the script with the line endings set by `_bxt_tas_recording_crlf`.
+fn serialize_script(
+    marker: MainThreadMarker,
+    hltas: &HLTAS,
+    writer: impl io::Write,
+) -> Result<(), Box<dyn Error>> {
+    let result = if BXT_TAS_RECORDING_CRLF.as_bool(marker) {
+        hltas.to_writer(CrlfWriter(writer))
+    } else {
+        hltas.to_writer(writer)
+    };
+    Ok(result?)
+}
+
+/// Writer converting LF line endings to CRLF.
+struct CrlfWriter<W>(W);
+
+impl<W: io::Write> io::Write for CrlfWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for (i, line) in buf.split(|&byte| byte == b'\n').enumerate() {
+            if i > 0 {
+                self.0.write_all(b"\r\n")?;
+            }
+            self.0.write_all(line)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/// Returns the file name with another extension appended, like `run.hltas.tmp`.
+fn with_appended_extension(filename: &Path, extension: &str) -> PathBuf {
+    let mut appended = filename.as_os_str().to_owned();
+    appended.push(".");
+    appended.push(extension);
+    appended.into()
+}
+
+/// Drops the recorder without writing anything, removing its autosave and the sink outputs.
+fn discard(recorder: Recorder) {
+    remove_autosave(&part_filename(&recorder.filename, recorder.split_count + 1));
+
+    for sink in recorder.sinks {
+        sink.discard();
+    }
+}
+
+/// Requests the recording to stop at the end of the frame, returning whether there's a recording.
+fn request_stop(state: &mut State) -> bool {
+    match state {
+        State::Recording(recorder) => {
+            recorder.stop_requested = true;
+            true
+        }
+        State::Idle => false,
+    }
+}
+
+/// Takes the recorder out of the state if it was requested to stop.
+fn take_stopped_recorder(state: &mut State) -> Option<Recorder> {
+    if !matches!(state, State::Recording(recorder) if recorder.stop_requested) {
+        return None;
+    }
+
+    match mem::replace(state, State::Idle) {
+        State::Recording(recorder) => Some(recorder),
+        State::Idle => unreachable!(),
+    }
+}
+
+/// Writes out the last part of the recording, returning whether it succeeded.
+fn finish(marker: MainThreadMarker, recorder: Recorder) -> bool {
+    let Recorder {
+        mut hltas,
+        filename,
+        split_count,
+        mut sinks,
+        sinks_only,
+        pending_record,
+        assists_suspended,
+        suspended_cvars,
+        ..
+    } = recorder;
+
+    restore_assists(marker, assists_suspended, &suspended_cvars);
+
+    if let Some(record) = pending_record {
+        feed_sinks(marker, &mut sinks, |sink| sink.on_frame(&record));
+    }
+    let mut succeeded = true;
+    for mut sink in sinks {
+        if let Err(err) = sink.finish() {
+            print_error(
+                marker,
+                &format!("Error writing {}: {}\n", sink.path().display(), err),
+            );
+            succeeded = false;
+        }
+    }
+
+    if sinks_only {
+        return succeeded;
+    }
+
+    // The frame bulks of a frame that didn't finish have no frame times to be written with.
+    drop_incomplete_frame_bulks(&mut hltas.lines);
+
+    let filename = part_filename(&filename, split_count + 1);
+    remove_autosave(&filename);
+    write_script(marker, hltas, &filename) && succeeded
+}
+
+/// Returns the file name of the autosave of the script.
+fn autosave_filename(filename: &Path) -> PathBuf {
+    with_appended_extension(filename, "autosave")
+}
+
+/// Removes the autosave of the script, if there's one.
+fn remove_autosave(filename: &Path) {
+    // The autosave might not exist, for example if autosaving is disabled.
+    let _ = fs::remove_file(autosave_filename(filename));
+}
+
+/// Autosaves the recording if `_bxt_tas_recording_autosave_interval` has passed.
+fn autosave(marker: MainThreadMarker, recorder: &mut Recorder) {
+    let interval = BXT_TAS_RECORDING_AUTOSAVE_INTERVAL.as_f32(marker);
+    if interval <= 0. {
+        return;
+    }
+
+    let now = Instant::now();
+    let last_autosave = *recorder.last_autosave.get_or_insert(now);
+    if now.duration_since(last_autosave).as_secs_f32() < interval {
+        return;
+    }
+    recorder.last_autosave = Some(now);
+
+    let filename = part_filename(&recorder.filename, recorder.split_count + 1);
+    write_script(
+        marker,
+        recorder.hltas.clone(),
+        &autosave_filename(&filename),
+    );
+}
+
+/// Starts recording and playing the next demo of the batch, or ends the batch if there are none.
+unsafe fn play_next_batch_demo(marker: MainThreadMarker) {
+    let mut batch_ref = BATCH.borrow_mut(marker);
+    let batch = match &mut *batch_ref {
+        Some(batch) => batch,
+        None => return,
+    };
+
+    while let Some(demo) = batch.demos.pop() {
+        // The engine stops the demo loop if it can't open the demo, so check that beforehand.
+        if let Err(err) = File::open(&demo) {
+            con_print(
+                marker,
+                &format!("Error opening {}: {}\n", demo.display(), err),
+            );
+            batch.failed += 1;
+            continue;
+        }
+
+        let relative = demo.strip_prefix(&batch.game_dir).unwrap_or(&demo);
+        let command = format!("playdemo \"{}\"\n", relative.display());
+
+        // Make the engine call Host_NextDemo() when the demo ends.
+        (*engine::cls_demos.get(marker)).demonum = 0;
+
+        start(marker, demo.with_extension("hltas"), HLTAS::default());
+        engine::prepend_command(marker, &command);
+        return;
+    }
+
+    (*engine::cls_demos.get(marker)).demonum = -1;
+    con_print(
+        marker,
+        &format!(
+            "Converted {} demos, {} failed\n",
+            batch.succeeded, batch.failed
+        ),
+    );
+    *batch_ref = None;
+}
+
+/// Finishes the recording of the demo that ended and plays the next one.
+///
+/// Returns `false` if no demos are being converted, in which case the engine should handle the
+/// next demo as usual.
+pub unsafe fn on_host_next_demo(marker: MainThreadMarker) -> bool {
+    if BATCH.borrow(marker).is_none() {
+        return false;
+    }
+
+    let recorder = match mem::replace(&mut *STATE.borrow_mut(marker), State::Idle) {
+        State::Recording(recorder) => Some(recorder),
+        State::Idle => None,
+    };
+    let succeeded = match recorder {
+        Some(recorder)
+            if recorder
+                .hltas
+                .lines
+                .iter()
+                .any(|line| matches!(line, Line::FrameBulk(_))) =>
+        {
+            finish(marker, recorder)
+        }
+        _ => false,
+    };
+
+    if let Some(batch) = &mut *BATCH.borrow_mut(marker) {
+        if succeeded {
+            batch.succeeded += 1;
+        } else {
+            batch.failed += 1;
+        }
+    }
+
+    play_next_batch_demo(marker);
+    true
+}
+
+/// Compares the player origin against the recorded one for the self-test.
+///
+/// # Safety
+///
+/// The server data must not be modified while this function is running.
+unsafe fn check_selftest(marker: MainThreadMarker) {
+    let mut selftest = SELFTEST.borrow_mut(marker);
+    let test = match &mut *selftest {
+        Some(test) => test,
+        None => return,
+    };
+
+    // Ticks are counted the same way as during the recording.
+    let client_state = (*engine::cls.get(marker)).state;
+    if client_state != 4 && client_state != 5 {
+        return;
+    }
+    test.tick += 1;
+
+    let last_tick = test.origins.last().map_or(0, |&(tick, _)| tick);
+    if test.tick > last_tick {
+        con_print(
+            marker,
+            &format!(
+                "Self-test passed: the replay matched the recording for {} ticks\n",
+                last_tick
+            ),
+        );
+        *selftest = None;
+        return;
+    }
+
+    let recorded = match test.origins.iter().find(|&&(tick, _)| tick == test.tick) {
+        Some(&(_, position)) => position,
+        // The recording had no player on this tick.
+        None => return,
+    };
+    let replayed = match engine::player_edict(marker) {
+        Some(edict) => player_position(edict),
+        None => return,
+    };
+
+    if origins_diverged(recorded.origin, replayed.origin)
+        || angles_diverged(recorded.angles, replayed.angles)
+    {
+        con_print(
+            marker,
+            &format!(
+                "Self-test failed: the replay diverged on tick {}\n  \
+                 recorded: {} {} {}, angles {} {} {}\n  \
+                 replayed: {} {} {}, angles {} {} {}\n",
+                test.tick,
+                recorded.origin[0],
+                recorded.origin[1],
+                recorded.origin[2],
+                recorded.angles[0],
+                recorded.angles[1],
+                recorded.angles[2],
+                replayed.origin[0],
+                replayed.origin[1],
+                replayed.origin[2],
+                replayed.angles[0],
+                replayed.angles[1],
+                replayed.angles[2],
+            ),
+        );
+        *selftest = None;
+    }
+}
+
+/// Returns whether the replayed origin is farther than [`SELFTEST_THRESHOLD`] from the recorded one.
+fn origins_diverged(recorded: [f32; 3], replayed: [f32; 3]) -> bool {
+    let distance_squared: f32 = recorded
+        .iter()
+        .zip(replayed)
+        .map(|(a, b)| (a - b) * (a - b))
+        .sum();
+    distance_squared > SELFTEST_THRESHOLD * SELFTEST_THRESHOLD
+}
+
+/// Returns whether any replayed view angle differs by more than [`SELFTEST_ANGLE_THRESHOLD`] from
+/// the recorded one.
+fn angles_diverged(recorded: [f32; 3], replayed: [f32; 3]) -> bool {
+    recorded.iter().zip(replayed).any(|(a, b)| {
+        // The same angle can come out as 359 or -1.
+        let difference = (a - b).rem_euclid(360.);
+        difference.min(360. - difference) > SELFTEST_ANGLE_THRESHOLD
+    })
+}
+
+unsafe fn change_map(marker: MainThreadMarker, recorder: &mut Recorder, map: String) {
+    if BXT_TAS_RECORDING_SPLIT_ON_MAP.as_bool(marker) {
+        let filename = part_filename(&recorder.filename, recorder.split_count + 1);
+        let next_filename = part_filename(&recorder.filename, recorder.split_count + 2);
+
+        // The seeds of the next part are set from its first command.
+        let properties = Properties {
+            seeds: None,
+            demo: recorder.hltas.properties.demo.as_ref().and(
+                next_filename
+                    .file_stem()
+                    .and_then(|stem| stem.to_str())
+                    .map(str::to_owned),
+            ),
+            ..recorder.hltas.properties.clone()
+        };
+        let hltas = mem::replace(
+            &mut recorder.hltas,
+            HLTAS {
+                properties,
+                lines: Vec::new(),
+            },
+        );
+        remove_autosave(&filename);
+        write_script(marker, hltas, &filename);
+        recorder.split_count += 1;
+
+        // The next part has to set up the RNG and the angles on its own. The pending frame data
+        // belongs to the frame being recorded, so it's kept for the next part.
+        recorder.last_shared_seed = None;
+        recorder.predicted_rng_state = None;
+        recorder.last_viewangles = None;
+
+        if let Some(comment) = start_comment(marker) {
+            recorder.hltas.lines.push(comment);
+        }
+
+        con_print(
+            marker,
+            &format!("Continuing the recording in {}\n", next_filename.display()),
+        );
+    } else {
+        recorder
+            .hltas
+            .lines
+            .push(Line::Comment(format!(" Map changed to {}", map)));
+    }
+
+    recorder.current_map = Some(map);
+    recorder.last_origin = None;
+}
+
+/// Adds a comment if the player got teleported since the last frame.
+///
+/// # Safety
+///
+/// The server data must not be modified while this function is running.
+unsafe fn mark_teleport(marker: MainThreadMarker, recorder: &mut Recorder) {
+    let (origin, velocity) = match engine::player_edict(marker) {
+        Some(edict) => {
+            // SAFETY: we're not calling any engine functions while the reference is alive.
+            let edict = edict.as_ref();
+            (edict.v.origin, edict.v.velocity)
+        }
+        None => {
+            recorder.last_origin = None;
+            return;
+        }
+    };
+
+    let last_origin = match recorder.last_origin.replace(origin) {
+        Some(last_origin) => last_origin,
+        None => return,
+    };
+
+    let threshold = BXT_TAS_RECORDING_TELEPORT_THRESHOLD.as_f32(marker);
+    if threshold <= 0. {
+        return;
+    }
+
+    let frame_time = *engine::host_frametime.get(marker) as f32;
+    if let Some(distance) = teleport_distance(last_origin, origin, velocity, frame_time, threshold)
+    {
+        recorder.hltas.lines.push(Line::Comment(format!(
+            " Teleported by {:.1} units",
+            distance
+        )));
+    }
+}
+
+/// Adds a comment if the player's water level changed since the last frame.
+///
+/// # Safety
+///
+/// The server data must not be modified while this function is running.
+unsafe fn mark_water_level(marker: MainThreadMarker, recorder: &mut Recorder) {
+    let water_level = engine::player_edict(marker).map(|edict| {
+        // SAFETY: we're not calling any engine functions while the reference is alive.
+        edict.as_ref().v.waterlevel
+    });
+    let water_level = match water_level {
+        Some(water_level) => water_level,
+        None => return,
+    };
+
+    if let Some(last) = recorder.last_water_level.replace(water_level) {
+        if let Some(comment) = water_level_comment(last, water_level) {
+            recorder.hltas.lines.push(comment);
+        }
+    }
+}
+
+/// Returns the comment marking the change of the water level, if it changed.
+fn water_level_comment(last: c_int, current: c_int) -> Option<Line> {
+    let name = |water_level| match water_level {
+        0 => "dry".to_owned(),
+        1 => "feet".to_owned(),
+        2 => "waist".to_owned(),
+        3 => "eyes".to_owned(),
+        _ => water_level.to_string(),
+    };
+
+    if last == current {
+        return None;
+    }
+
+    Some(Line::Comment(format!(
+        " Water level: {} -> {}",
+        name(last),
+        name(current)
+    )))
+}
+
+/// Returns the distance between the origins if it's too large to be covered with the velocity.
+///
+/// The player covers at most `|velocity| * frame_time` in a frame, so anything beyond that by more
+/// than `threshold` is a teleport.
+fn teleport_distance(
+    last_origin: [f32; 3],
+    origin: [f32; 3],
+    velocity: [f32; 3],
+    frame_time: f32,
+    threshold: f32,
+) -> Option<f32> {
+    let length = |v: [f32; 3]| (v[0] * v[0] + v[1] * v[1] + v[2] * v[2]).sqrt();
+
+    let distance = length([
+        origin[0] - last_origin[0],
+        origin[1] - last_origin[1],
+        origin[2] - last_origin[2],
+    ]);
+    let covered = length(velocity) * frame_time;
+
+    if distance - covered > threshold {
+        Some(distance)
+    } else {
+        None
+    }
+}
+
+/// Draws the recording indicator on the HUD.
+///
+/// # Safety
+///
+/// Must be called from the HUD drawing, where drawing text is possible.
+pub unsafe fn on_hud_redraw(marker: MainThreadMarker) {
+    if !engine::Draw_String.is_set(marker) || !BXT_TAS_RECORDING_HUD.as_bool(marker) {
+        return;
+    }
+
+    let state = STATE.borrow(marker);
+    let recorder = match &*state {
+        State::Recording(recorder) => recorder,
+        State::Idle => return,
+    };
+
+    let text = match CString::new(hud_text(&recorder.filename, recorder.frame_count)) {
+        Ok(text) => text,
+        Err(_) => return,
+    };
+    // Draw_String() takes a mutable pointer but doesn't write through it.
+    engine::Draw_String.get(marker)(10, 10, text.as_ptr() as *mut c_char);
+}
+
+/// Returns the text of the recording indicator.
+fn hud_text(filename: &Path, frame_count: u64) -> String {
+    let name = filename
+        .file_name()
+        .unwrap_or(filename.as_os_str())
+        .to_string_lossy();
+    format!("* REC {} {}", name, frame_count)
+}
+
+/// Converts the command into a frame bulk, along with the console commands it needs.
+///
+/// The frame bulk has no frame time, as it's known only at the end of the physics frame. `up` and
+/// `down` are whether `+moveup` and `+movedown` are held, since the command has no buttons for them.
+/// `movespeedkey` is the value of `cl_movespeedkey`, which scales the moves while `+speed` is held.
+fn usercmd_to_frame_bulk(
+    keys: &mut Keys,
+    cmd: &usercmd_s,
+    up: bool,
+    down: bool,
+    movespeedkey: f32,
+) -> (FrameBulk, Moves) {
+    let mut frame_bulk = FrameBulk {
+        auto_actions: Default::default(),
+        movement_keys: Default::default(),
+        action_keys: Default::default(),
+        frame_time: Default::default(),
+        pitch: Default::default(),
+        frame_count: 1.try_into().unwrap(),
+        console_command: Default::default(),
+    };
+
+    let buttons = Buttons::from_bits_truncate(cmd.buttons);
+
+    if buttons.contains(Buttons::IN_FORWARD) {
+        frame_bulk.movement_keys.forward = true;
+    }
+    if buttons.contains(Buttons::IN_BACK) {
+        frame_bulk.movement_keys.back = true;
+    }
+    if buttons.contains(Buttons::IN_MOVELEFT) {
+        frame_bulk.movement_keys.left = true;
+    }
+    if buttons.contains(Buttons::IN_MOVERIGHT) {
+        frame_bulk.movement_keys.right = true;
+    }
+    if up {
+        frame_bulk.movement_keys.up = true;
+    }
+    if down {
+        frame_bulk.movement_keys.down = true;
+    }
+    add_action_keys(&mut frame_bulk.action_keys, buttons);
+    frame_bulk.auto_actions.movement = Some(AutoMovement::SetYaw(cmd.viewangles[1]));
+    frame_bulk.pitch = Some(cmd.viewangles[0]);
+
+    // Walking is replayed with `+speed` along with the speed variables it scales.
+    let walking = buttons.contains(Buttons::IN_RUN) && movespeedkey > 0.;
+    let mut walk_commands = Vec::new();
+    if walking && !keys.walking {
+        walk_commands.push(format!("cl_movespeedkey {}", movespeedkey));
+        walk_commands.push("+speed".to_owned());
+    } else if !walking && keys.walking {
+        walk_commands.push("-speed".to_owned());
+    }
+    keys.walking = walking;
+
+    let walk = if walking { f64::from(movespeedkey) } else { 1. };
+    let mut moves = record_movement(keys, cmd, &mut frame_bulk.movement_keys, walk);
+    walk_commands.append(&mut moves.commands);
+    moves.commands = walk_commands;
+
+    (frame_bulk, moves)
+}
+
+/// Replaces the derived speeds in the commands with the current speed variable values where they
+/// match.
+///
+/// The speeds are derived from the `f32` moves, which the client computed from the speed variables
+/// with `f32` rounding, so they can come out as, for example, `100.00000762939453` when the player
+/// has `cl_sidespeed 100`. A derived speed within a few `f32` rounding errors of the variable is the
+/// variable value. Speeds that differ, like with the moves clamped by the max speed, are kept as
+/// derived.
+fn reconcile_speeds(commands: &mut [String], cvar: impl Fn(&str) -> Option<f32>) {
+    for command in commands {
+        let (name, derived) = match command.split_once(' ') {
+            Some((name, value)) => match value.parse::<f64>() {
+                Ok(value) => (name, value),
+                Err(_) => continue,
+            },
+            None => continue,
+        };
+
+        let value = match cvar(name) {
+            Some(value) => value,
+            None => continue,
+        };
+
+        let value_f64 = f64::from(value);
+        if (derived - value_f64).abs() <= value_f64.abs() * 4. * f64::from(f32::EPSILON) {
+            *command = format!("{} {}", name, value);
+        }
+    }
+}
+
+/// Speed variables set by the recorded moves.
+const SPEED_CVARS: &[&str] = &[
+    "cl_forwardspeed",
+    "cl_backspeed",
+    "cl_sidespeed",
+    "cl_upspeed",
+    "cl_movespeedkey",
+];
+
+/// Removes the commands setting the speed variables, keeping the rest.
+fn remove_speed_commands(commands: &mut Vec<String>) {
+    commands.retain(|command| {
+        let name = command
+            .split_once(' ')
+            .map_or(&command[..], |(name, _)| name);
+        !SPEED_CVARS.contains(&name)
+    });
+}
+
+/// Returns the number of decimals set in `_bxt_tas_recording_angle_decimals`, if any.
+fn angle_decimals(marker: MainThreadMarker) -> Option<u32> {
+    BXT_TAS_RECORDING_ANGLE_DECIMALS
+        .to_string(marker)
+        .trim()
+        .parse()
+        .ok()
+}
+
+/// Rounds the yaw and the pitch of the frame bulk to the number of decimals.
+///
+/// With `None`, the angles are kept as is: they are written with enough digits to read back the
+/// same `f32`.
+fn round_angles(frame_bulk: &mut FrameBulk, decimals: Option<u32>) {
+    let decimals = match decimals {
+        Some(decimals) => decimals,
+        None => return,
+    };
+
+    let round = |angle: f32| {
+        let scale = 10f64.powi(decimals.min(9) as i32);
+        ((f64::from(angle) * scale).round() / scale) as f32
+    };
+
+    if let Some(AutoMovement::SetYaw(yaw)) = &mut frame_bulk.auto_actions.movement {
+        *yaw = round(*yaw);
+    }
+    if let Some(pitch) = &mut frame_bulk.pitch {
+        *pitch = round(*pitch);
+    }
+}
+
+/// Sets the action keys held in `buttons`, leaving the other keys as is.
+///
+/// The keys are recorded as held on every command they're held in. The replay keeps a key held
+/// across consecutive frame bulks that have it, so a jump held through the air stays a single press
+/// which the game doesn't act on again when landing, while a tap is a one frame press followed by a
+/// release. The same goes for attacking: an automatic weapon spray becomes a single multi-frame span
+/// once the identical frame bulks are joined, while semi-automatic taps stay separate presses.
+fn add_action_keys(action_keys: &mut ActionKeys, buttons: Buttons) {
+    if buttons.contains(Buttons::IN_JUMP) {
+        action_keys.jump = true;
+    }
+    if buttons.contains(Buttons::IN_DUCK) {
+        action_keys.duck = true;
+    }
+    if buttons.contains(Buttons::IN_USE) {
+        action_keys.use_ = true;
+    }
+    if buttons.contains(Buttons::IN_ATTACK) {
+        action_keys.attack_1 = true;
+    }
+    if buttons.contains(Buttons::IN_ATTACK2) {
+        action_keys.attack_2 = true;
+    }
+    if buttons.contains(Buttons::IN_RELOAD) {
+        action_keys.reload = true;
+    }
+}
+
+/// Returns the buttons of the held action keys, the reverse of [`add_action_keys()`].
+fn action_key_buttons(action_keys: &ActionKeys) -> Buttons {
+    let mut buttons = Buttons::empty();
+    buttons.set(Buttons::IN_JUMP, action_keys.jump);
+    buttons.set(Buttons::IN_DUCK, action_keys.duck);
+    buttons.set(Buttons::IN_USE, action_keys.use_);
+    buttons.set(Buttons::IN_ATTACK, action_keys.attack_1);
+    buttons.set(Buttons::IN_ATTACK2, action_keys.attack_2);
+    buttons.set(Buttons::IN_RELOAD, action_keys.reload);
+    buttons
+}
+
+/// Adds the action keys of a later command of a command-split sequence to its frame bulk.
+///
+/// Buttons pressed only on the later commands would otherwise be lost. Jump and duck are left out
+/// if the frame bulk replaced them with a leave ground action.
+fn add_split_action_keys(frame_bulk: &mut FrameBulk, buttons: Buttons) {
+    let mut buttons = buttons;
+    if frame_bulk.auto_actions.leave_ground_action.is_some() {
+        buttons.remove(Buttons::IN_JUMP | Buttons::IN_DUCK);
+    }
+
+    add_action_keys(&mut frame_bulk.action_keys, buttons);
+}
+
+/// Returns the command setting the view angles explicitly, if needed.
+fn angle_anchor_command(
+    marker: MainThreadMarker,
+    recorder: &mut Recorder,
+    viewangles: [f32; 3],
+) -> Option<String> {
+    let threshold = BXT_TAS_RECORDING_ANGLE_ANCHOR_THRESHOLD.as_f32(marker);
+    let needs_anchor = match recorder.last_viewangles {
+        None => true,
+        Some(last) => threshold > 0. && angles_jumped(last, viewangles, threshold),
+    };
+    recorder.last_viewangles = Some(viewangles);
+
+    if needs_anchor {
+        Some(format!(
+            "bxt_set_angles {} {}",
+            viewangles[0], viewangles[1]
+        ))
+    } else {
+        None
+    }
+}
+
+/// Prints the error to the console and remembers it for `bxt_tas_recording_last_error`.
+fn print_error(marker: MainThreadMarker, message: &str) {
+    con_print(marker, message);
+    *LAST_ERROR.borrow_mut(marker) = Some(message.trim_end().to_owned());
+}
+
+/// Writes out a stopped recording and reports the result along with the frame time histogram.
+fn stop(marker: MainThreadMarker, recorder: Recorder) {
+    let histogram = frame_time_histogram(&recorder.frame_time_counts);
+    if finish(marker, recorder) {
+        *LAST_ERROR.borrow_mut(marker) = None;
+    }
+    con_print(marker, "Recording stopped\n");
+    con_print(marker, &histogram);
+}
+
+/// Formats the frame time histogram, with the most common frame times first.
+fn frame_time_histogram(counts: &[(String, u64)]) -> String {
+    /// Width of the bar of the most common frame time.
+    const BAR_WIDTH: u64 = 20;
+
+    let mut counts = counts.to_vec();
+    counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+
+    let total: u64 = counts.iter().map(|(_, count)| count).sum();
+    let max = counts.first().map_or(0, |(_, count)| *count);
+
+    let mut histogram = String::new();
+    for (frame_time, count) in counts {
+        let bar = "#".repeat((count * BAR_WIDTH).div_ceil(max) as usize);
+        writeln!(
+            histogram,
+            "{:>10} {:<20} {} ({:.1}%)",
+            frame_time,
+            bar,
+            count,
+            count as f64 * 100. / total as f64
+        )
+        .expect("writing to `String` should never error");
+    }
+
+    histogram
+}
+
+impl Recorder {
+    /// Updates the ground state at the start of a physics frame.
+    fn update_onground(&mut self, on_ground: bool) {
+        self.was_onground = self.is_onground;
+        self.is_onground = on_ground;
+    }
+
+    /// Records the commands in text added by the player.
+    ///
+    /// The commands are kept in order, so for example several weapon switches within one frame
+    /// replay in the same order.
+    fn record_bound_text(&mut self, text: &str) {
+        for command in split_commands(text) {
+            let name = match command.split_ascii_whitespace().next() {
+                Some(name) => name,
+                None => continue,
+            };
+
+            // Keep track of the up and down keys as they aren't present in the command buttons.
+            match name {
+                "+moveup" => self.up_held = true,
+                "-moveup" => self.up_held = false,
+                "+movedown" => self.down_held = true,
+                "-movedown" => self.down_held = false,
+                _ => (),
+            }
+
+            // The extra buttons are recorded from the command buttons.
+            let is_extra_button = matches!(
+                name.strip_prefix(&['+', '-'][..]),
+                Some(name) if self.extra_buttons.iter().any(|(_, command)| command == name)
+            );
+
+            if !self.paused && !is_extra_button && is_recorded_command(name) {
+                self.pending_bound_commands.push(command.to_string());
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::frames::{count_frame_time, fill_frame_bulks};
+    use super::transforms::join_frame_bulks;
+    use super::*;
+
+    fn cmd(forwardmove: f32, sidemove: f32, upmove: f32) -> usercmd_s {
+        usercmd_s {
+            lerp_msec: 0,
+            msec: 10,
+            viewangles: [0.; 3],
+            forwardmove,
+            sidemove,
+            upmove,
+            lightlevel: 0,
+            buttons: 0,
+            impulse: 0,
+            weaponselect: 0,
+            impact_index: 0,
+            impact_position: [0.; 3],
+        }
+    }
+
+    fn held(up: bool, down: bool) -> MovementKeys {
+        MovementKeys {
+            up,
+            down,
+            ..Default::default()
+        }
+    }
+
+    fn record(
+        keys: &mut Keys,
+        cmd: usercmd_s,
+        mut movement_keys: MovementKeys,
+    ) -> (MovementKeys, String) {
+        let moves = record_movement(keys, &cmd, &mut movement_keys, 1.);
+        keys.clear_impulses();
+        (movement_keys, moves.commands.join(";"))
+    }
+
+    #[test]
+    fn usercmd_buttons_and_angles() {
+        let mut keys = Keys::default();
+        let cmd = usercmd_s {
+            viewangles: [10., 90., 0.],
+            buttons: (Buttons::IN_JUMP | Buttons::IN_DUCK | Buttons::IN_ATTACK2).bits(),
+            ..cmd(0., 0., 0.)
+        };
+
+        let (frame_bulk, moves) = usercmd_to_frame_bulk(&mut keys, &cmd, false, false, 0.3);
+
+        assert!(frame_bulk.action_keys.jump);
+        assert!(frame_bulk.action_keys.duck);
+        assert!(frame_bulk.action_keys.attack_2);
+        assert!(!frame_bulk.action_keys.attack_1);
+        assert!(!frame_bulk.action_keys.use_);
+        assert_eq!(
+            frame_bulk.auto_actions.movement,
+            Some(AutoMovement::SetYaw(90.))
+        );
+        assert_eq!(frame_bulk.pitch, Some(10.));
+        assert!(frame_bulk.frame_time.is_empty());
+        assert!(moves.commands.is_empty());
+    }
+
+    #[test]
+    fn speed_commands_removed() {
+        let mut keys = Keys::default();
+        let walk_cmd = usercmd_s {
+            buttons: (Buttons::IN_FORWARD | Buttons::IN_MOVELEFT | Buttons::IN_RUN).bits(),
+            ..cmd(100., -200., 0.)
+        };
+
+        let (frame_bulk, moves) = usercmd_to_frame_bulk(&mut keys, &walk_cmd, false, false, 0.5);
+        let mut commands = moves.commands;
+        remove_speed_commands(&mut commands);
+
+        assert!(frame_bulk.movement_keys.forward);
+        assert!(frame_bulk.movement_keys.left);
+        assert_eq!(commands, ["+speed"]);
+
+        let (frame_bulk, moves) =
+            usercmd_to_frame_bulk(&mut keys, &cmd(0., 0., 320.), false, false, 0.5);
+        let mut commands = moves.commands;
+        remove_speed_commands(&mut commands);
+
+        assert!(frame_bulk.movement_keys.up);
+        assert_eq!(commands, ["-speed"]);
+
+        let (_, moves) = usercmd_to_frame_bulk(&mut keys, &cmd(0., 0., 320.), false, false, 0.5);
+        let mut commands = moves.commands;
+        remove_speed_commands(&mut commands);
+
+        assert!(commands.is_empty());
+    }
+
+    #[test]
+    fn attack2_on_split_command() {
+        let mut keys = Keys::default();
+        let (mut frame_bulk, _) =
+            usercmd_to_frame_bulk(&mut keys, &cmd(0., 0., 0.), false, false, 0.3);
+        assert!(!frame_bulk.action_keys.attack_2);
+
+        // IN_ATTACK2 goes down only on the second command of the split.
+        add_split_action_keys(&mut frame_bulk, Buttons::IN_ATTACK2);
+        assert!(frame_bulk.action_keys.attack_2);
+        assert!(!frame_bulk.action_keys.attack_1);
+    }
+
+    #[test]
+    fn split_command_keeps_leave_ground_action() {
+        let mut frame_bulk = FrameBulk::with_frame_time(String::new());
+        frame_bulk.auto_actions.leave_ground_action = Some(LeaveGroundAction {
+            speed: LeaveGroundActionSpeed::Optimal,
+            times: Times::Limited(NonZeroU32::new(1).unwrap()),
+            type_: LeaveGroundActionType::Jump,
+        });
+
+        add_split_action_keys(&mut frame_bulk, Buttons::IN_JUMP | Buttons::IN_USE);
+        assert!(!frame_bulk.action_keys.jump);
+        assert!(frame_bulk.action_keys.use_);
+    }
+
+    #[test]
+    fn usercmd_movement() {
+        let mut keys = Keys::default();
+        let cmd = usercmd_s {
+            buttons: (Buttons::IN_FORWARD | Buttons::IN_MOVERIGHT).bits(),
+            ..cmd(200., 200., -160.)
+        };
+
+        let (frame_bulk, moves) = usercmd_to_frame_bulk(&mut keys, &cmd, false, true, 0.3);
+
+        assert_eq!(
+            frame_bulk.movement_keys,
+            MovementKeys {
+                forward: true,
+                right: true,
+                down: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(
+            moves.commands,
+            ["cl_forwardspeed 400", "cl_sidespeed 400", "cl_upspeed 320"]
+        );
+        assert!(!moves.approximated);
+    }
+
+    #[test]
+    fn walking() {
+        let mut keys = Keys::default();
+        let walk_cmd = |forwardmove| usercmd_s {
+            buttons: (Buttons::IN_FORWARD | Buttons::IN_RUN).bits(),
+            ..cmd(forwardmove, 0., 0.)
+        };
+
+        // `+speed` halves the move on top of the key press impulse, so the recorded speed is the
+        // move value scaled back.
+        let (_, moves) = usercmd_to_frame_bulk(&mut keys, &walk_cmd(100.), false, false, 0.5);
+        assert_eq!(
+            moves.commands,
+            ["cl_movespeedkey 0.5", "+speed", "cl_forwardspeed 400"]
+        );
+
+        keys.clear_impulses();
+        let (_, moves) = usercmd_to_frame_bulk(&mut keys, &walk_cmd(200.), false, false, 0.5);
+        assert_eq!(moves.commands, ["cl_forwardspeed 400"]);
+
+        let cmd = usercmd_s {
+            buttons: Buttons::IN_FORWARD.bits(),
+            ..cmd(400., 0., 0.)
+        };
+        let (_, moves) = usercmd_to_frame_bulk(&mut keys, &cmd, false, false, 0.5);
+        assert_eq!(moves.commands, ["-speed", "cl_forwardspeed 400"]);
+    }
+
+    #[test]
+    fn upmove() {
+        let mut keys = Keys::default();
+
+        // No upmove and no keys results in no commands.
+        let (movement_keys, commands) = record(&mut keys, cmd(0., 0., 0.), held(false, false));
+        assert_eq!(movement_keys, held(false, false));
+        assert_eq!(commands, "");
+
+        // The frame the key is pressed on has half the speed.
+        let (movement_keys, commands) = record(&mut keys, cmd(0., 0., 160.), held(true, false));
+        assert_eq!(movement_keys, held(true, false));
+        assert_eq!(commands, "cl_upspeed 320");
+
+        let (movement_keys, commands) = record(&mut keys, cmd(0., 0., 320.), held(true, false));
+        assert_eq!(movement_keys, held(true, false));
+        assert_eq!(commands, "cl_upspeed 320");
+
+        // Down is pressed on this frame, so both keys are kept.
+        let (movement_keys, commands) = record(&mut keys, cmd(0., 0., 100.), held(true, true));
+        assert_eq!(movement_keys, held(true, true));
+        assert_eq!(commands, "cl_upspeed 200");
+
+        // Both held for a while with negative upmove keeps only down.
+        let (movement_keys, commands) = record(&mut keys, cmd(0., 0., -160.), held(true, true));
+        assert_eq!(movement_keys, held(false, true));
+        assert_eq!(commands, "cl_upspeed 160");
+
+        let (movement_keys, commands) = record(&mut keys, cmd(0., 0., -320.), held(false, true));
+        assert_eq!(movement_keys, held(false, true));
+        assert_eq!(commands, "cl_upspeed 320");
+
+        // Upmove without keys (e.g. from a joystick) uses the key in the direction of the move,
+        // which is pressed on this frame.
+        let mut keys = Keys::default();
+        let (movement_keys, commands) = record(&mut keys, cmd(0., 0., -160.), held(false, false));
+        assert_eq!(movement_keys, held(false, true));
+        assert_eq!(commands, "cl_upspeed 320");
+    }
+
+    #[test]
+    fn analog_forwardmove() {
+        let mut keys = Keys::default();
+        let mut replay = Key::default();
+
+        // The first frame is replayed with the key press impulse.
+        for expected in ["cl_forwardspeed 275", "cl_forwardspeed 137.5"] {
+            let (movement_keys, commands) =
+                record(&mut keys, cmd(137.5, 0., 0.), MovementKeys::default());
+            assert_eq!(
+                movement_keys,
+                MovementKeys {
+                    forward: true,
+                    ..Default::default()
+                }
+            );
+            assert_eq!(commands, expected);
+
+            replay.update(true);
+            let speed: f64 = commands.split(' ').nth(1).unwrap().parse().unwrap();
+            assert_eq!(speed * replay.multiplier(), 137.5);
+            replay.clear_impulses();
+        }
+
+        let (movement_keys, commands) =
+            record(&mut keys, cmd(0., -137.5, 0.), MovementKeys::default());
+        assert_eq!(
+            movement_keys,
+            MovementKeys {
+                left: true,
+                ..Default::default()
+            }
+        );
+        assert_eq!(commands, "cl_sidespeed 275");
+
+        // The replayed key move is scaled by `+speed` too.
+        let mut keys = Keys::default();
+        let moves = record_movement(
+            &mut keys,
+            &cmd(100., 0., 0.),
+            &mut MovementKeys::default(),
+            0.5,
+        );
+        assert_eq!(moves.commands, ["cl_forwardspeed 400"]);
+    }
+
+    #[test]
+    fn upmove_with_other_moves() {
+        let mut keys = Keys::default();
+
+        let movement_keys = MovementKeys {
+            forward: true,
+            left: true,
+            up: true,
+            ..Default::default()
+        };
+        let (result, commands) = record(&mut keys, cmd(200., -100., 160.), movement_keys);
+        assert_eq!(result, movement_keys);
+        assert_eq!(
+            commands,
+            "cl_forwardspeed 400;cl_sidespeed 200;cl_upspeed 320"
+        );
+    }
+
+    #[test]
+    fn swimming() {
+        let mut keys = Keys::default();
+        let swim_cmd = |pitch| usercmd_s {
+            viewangles: [pitch, 90., 0.],
+            buttons: Buttons::IN_FORWARD.bits(),
+            ..cmd(400., 0., 320.)
+        };
+
+        // Swimming up while looking down, the pitch decides the direction along with the upmove.
+        let (frame_bulk, moves) =
+            usercmd_to_frame_bulk(&mut keys, &swim_cmd(45.), true, false, 0.3);
+        assert_eq!(frame_bulk.pitch, Some(45.));
+        assert!(frame_bulk.movement_keys.forward && frame_bulk.movement_keys.up);
+        assert_eq!(moves.commands, ["cl_forwardspeed 800", "cl_upspeed 640"]);
+
+        // A level pitch is written as well rather than left out.
+        keys.clear_impulses();
+        let (frame_bulk, moves) = usercmd_to_frame_bulk(&mut keys, &swim_cmd(0.), true, false, 0.3);
+        assert_eq!(frame_bulk.pitch, Some(0.));
+        assert_eq!(moves.commands, ["cl_forwardspeed 400", "cl_upspeed 320"]);
+    }
+
+    #[test]
+    fn shared_seed_changes() {
+        assert_eq!(shared_seed_change(None, 100, false), None);
+        assert_eq!(shared_seed_change(None, 100, true), None);
+        assert_eq!(shared_seed_change(Some(99), 100, false), None);
+        assert_eq!(shared_seed_change(Some(u32::MAX), 0, false), None);
+        assert_eq!(shared_seed_change(Some(99), 100, true), Some(1));
+        assert_eq!(shared_seed_change(Some(90), 100, true), Some(10));
+        assert_eq!(shared_seed_change(Some(90), 100, false), Some(10));
+        assert_eq!(shared_seed_change(Some(100), 90, false), Some(u32::MAX - 9));
+    }
+
+    #[test]
+    fn reset_seeds() {
+        let seeded = RngState {
+            idum: -1_700_000_000,
+            ..Default::default()
+        };
+        assert_eq!(reset_seed(seeded), Some(-1_700_000_000));
+
+        let used = RngState {
+            idum: 12345,
+            iy: 678,
+            ..Default::default()
+        };
+        assert_eq!(reset_seed(used), None);
+        assert_eq!(reset_seed(RngState::default()), None);
+    }
+
+    #[test]
+    fn rng_set_commands() {
+        let first = RngState::default();
+        let second = RngState { idum: 1, ..first };
+
+        let command = rng_set_command(true, None, first).unwrap();
+        assert!(command.starts_with("_bxt_rng_set \"0 0 0 "));
+
+        assert_eq!(rng_set_command(false, Some(first), first), None);
+        assert_eq!(rng_set_command(false, None, second), None);
+
+        let commands: Vec<_> = [(first, first), (first, second)]
+            .into_iter()
+            .filter_map(|(predicted, current)| rng_set_command(false, Some(predicted), current))
+            .collect();
+        assert_eq!(commands, [format!("_bxt_rng_set \"{}\"", second)]);
+    }
+
+    #[test]
+    fn dont_join_across_use_release() {
+        let mut using = FrameBulk::with_frame_time("0.01".to_owned());
+        using.action_keys.use_ = true;
+        let released = FrameBulk::with_frame_time("0.01".to_owned());
+
+        let mut lines = vec![Line::FrameBulk(using.clone()); 5];
+        lines.extend(vec![Line::FrameBulk(released.clone()); 3]);
+        lines.push(Line::FrameBulk(using.clone()));
+        join_frame_bulks(&mut lines);
+
+        let with_count = |frame_bulk: &FrameBulk, count| FrameBulk {
+            frame_count: NonZeroU32::new(count).unwrap(),
+            ..frame_bulk.clone()
+        };
+        assert_eq!(
+            lines,
+            [
+                Line::FrameBulk(with_count(&using, 5)),
+                Line::FrameBulk(with_count(&released, 3)),
+                Line::FrameBulk(using),
+            ]
+        );
+    }
+
+    /// Records the commands with the given buttons and joins the resulting frame bulks.
+    fn record_buttons(buttons: &[Buttons]) -> Vec<Line> {
+        let mut keys = Keys::default();
+        let mut lines: Vec<Line> = buttons
+            .iter()
+            .map(|buttons| {
+                let cmd = usercmd_s {
+                    buttons: buttons.bits(),
+                    ..cmd(0., 0., 0.)
+                };
+                let (mut frame_bulk, moves) =
+                    usercmd_to_frame_bulk(&mut keys, &cmd, false, false, 0.3);
+                assert!(moves.commands.is_empty());
+                frame_bulk.frame_time = "0.01".to_owned();
+                Line::FrameBulk(frame_bulk)
+            })
+            .collect();
+        join_frame_bulks(&mut lines);
+        lines
+    }
+
+    /// Returns the state of the action key and the frame count of the frame bulks.
+    fn key_spans(lines: &[Line], key: fn(&ActionKeys) -> bool) -> Vec<(bool, u32)> {
+        lines
+            .iter()
+            .map(|line| match line {
+                Line::FrameBulk(frame_bulk) => {
+                    (key(&frame_bulk.action_keys), frame_bulk.frame_count.get())
+                }
+                _ => panic!("expected a frame bulk"),
+            })
+            .collect()
+    }
+
+    /// Returns the jump state and the frame count of the frame bulks.
+    fn jump_spans(lines: &[Line]) -> Vec<(bool, u32)> {
+        key_spans(lines, |keys| keys.jump)
+    }
+
+    pub(super) fn incomplete(console_command: &str) -> Line {
+        Line::FrameBulk(FrameBulk {
+            console_command: Some(console_command.to_owned()),
+            ..FrameBulk::with_frame_time(String::new())
+        })
+    }
+
+    pub(super) fn complete(frame_time: &str, console_command: &str) -> Line {
+        Line::FrameBulk(FrameBulk {
+            console_command: Some(console_command.to_owned()),
+            ..FrameBulk::with_frame_time(frame_time.to_owned())
+        })
+    }
+
+    #[test]
+    fn reload_cancel() {
+        // Reload tapped for a frame, cancelled, then held for two frames.
+        let lines = record_buttons(&[
+            Buttons::IN_RELOAD,
+            Buttons::IN_ATTACK,
+            Buttons::IN_RELOAD,
+            Buttons::IN_RELOAD,
+            Buttons::empty(),
+        ]);
+        let spans: Vec<_> = lines
+            .iter()
+            .map(|line| match line {
+                Line::FrameBulk(frame_bulk) => {
+                    (frame_bulk.action_keys.reload, frame_bulk.frame_count.get())
+                }
+                _ => panic!("expected a frame bulk"),
+            })
+            .collect();
+        assert_eq!(spans, [(true, 1), (false, 1), (true, 2), (false, 1)]);
+
+        // A reload tap in a command sharing the frame with the next one keeps the press.
+        let mut reloading = FrameBulk {
+            console_command: Some(String::new()),
+            ..FrameBulk::with_frame_time(String::new())
+        };
+        reloading.action_keys.reload = true;
+        let mut lines = vec![Line::FrameBulk(reloading), incomplete("")];
+        assert_eq!(
+            fill_frame_bulks(&mut lines, &[0.01], &[0.], &[String::new()]),
+            Ok(true)
+        );
+
+        let mut expected = FrameBulk::with_frame_time("0.01".to_owned());
+        expected.action_keys.reload = true;
+        assert_eq!(lines, [Line::FrameBulk(expected)]);
+    }
+
+    #[test]
+    fn frame_time_histograms() {
+        let mut counts = Vec::new();
+        count_frame_time(&mut counts, 0.001);
+        for _ in 0..3 {
+            count_frame_time(&mut counts, 0.010000000000000002);
+        }
+        count_frame_time(&mut counts, 0.01);
+        assert_eq!(counts, [("0.001".to_owned(), 1), ("0.01".to_owned(), 4)]);
+
+        assert_eq!(
+            frame_time_histogram(&counts),
+            "      0.01 #################### 4 (80.0%)\n     0.001 #####                1 (20.0%)\n"
+        );
+        assert_eq!(frame_time_histogram(&[]), "");
+    }
+
+    #[test]
+    fn held_jump() {
+        let lines = record_buttons(&[
+            Buttons::empty(),
+            Buttons::IN_JUMP,
+            Buttons::IN_JUMP,
+            Buttons::IN_JUMP,
+            Buttons::IN_JUMP,
+            Buttons::empty(),
+        ]);
+        assert_eq!(jump_spans(&lines), [(false, 1), (true, 4), (false, 1)]);
+    }
+
+    #[test]
+    fn tapped_jumps() {
+        let lines = record_buttons(&[
+            Buttons::IN_JUMP,
+            Buttons::empty(),
+            Buttons::empty(),
+            Buttons::IN_JUMP,
+            Buttons::empty(),
+            Buttons::IN_JUMP,
+        ]);
+        assert_eq!(
+            jump_spans(&lines),
+            [(true, 1), (false, 2), (true, 1), (false, 1), (true, 1)]
+        );
+    }
+
+    #[test]
+    fn held_attack() {
+        let mut buttons = vec![Buttons::empty()];
+        buttons.extend([Buttons::IN_ATTACK; 30]);
+        buttons.push(Buttons::empty());
+        let lines = record_buttons(&buttons);
+        assert_eq!(
+            key_spans(&lines, |keys| keys.attack_1),
+            [(false, 1), (true, 30), (false, 1)]
+        );
+    }
+
+    #[test]
+    fn tapped_attacks() {
+        let lines = record_buttons(&[
+            Buttons::IN_ATTACK,
+            Buttons::empty(),
+            Buttons::IN_ATTACK,
+            Buttons::empty(),
+            Buttons::empty(),
+            Buttons::IN_ATTACK,
+        ]);
+        assert_eq!(
+            key_spans(&lines, |keys| keys.attack_1),
+            [(true, 1), (false, 1), (true, 1), (false, 2), (true, 1)]
+        );
+    }
+
+    #[test]
+    fn teleports() {
+        // Moving fast isn't a teleport.
+        assert_eq!(
+            teleport_distance([0., 0., 0.], [200., 0., 0.], [2000., 0., 0.], 0.1, 64.),
+            None
+        );
+        assert_eq!(
+            teleport_distance([0., 0., 0.], [0., 3., 4.], [0., 0., 0.], 0.01, 64.),
+            None
+        );
+        assert_eq!(
+            teleport_distance([0., 0., 0.], [0., 300., 400.], [0., 0., 0.], 0.01, 64.),
+            Some(500.)
+        );
+        assert_eq!(
+            teleport_distance([0., 0., 0.], [1000., 0., 0.], [2000., 0., 0.], 0.1, 64.),
+            Some(1000.)
+        );
+    }
+
+    #[test]
+    fn angle_jumps() {
+        assert!(!angles_jumped([0., 0., 0.], [10., -10., 0.], 90.));
+        assert!(!angles_jumped([0., 170., 0.], [0., -170., 0.], 90.));
+        assert!(!angles_jumped([0., 0., 0.], [0., 0., 180.], 90.));
+        assert!(angles_jumped([0., 0., 0.], [0., 135., 0.], 90.));
+        assert!(angles_jumped([-60., 0., 0.], [60., 0., 0.], 90.));
+    }
+
+    #[test]
+    fn weapon_selection_order() {
+        let mut recorder = Recorder::default();
+        recorder.record_bound_text("impulse 3\n");
+        recorder.record_bound_text("+attack 241\n");
+        recorder.record_bound_text("slot1\n");
+        recorder.record_bound_text("-attack 241\n");
+        recorder.record_bound_text("lastinv;slot2\n");
+
+        // The attack key is recorded as a frame bulk action key.
+        assert_eq!(
+            recorder.pending_bound_commands.join(";"),
+            "impulse 3;slot1;lastinv;slot2"
+        );
+    }
+
+    #[test]
+    fn cheat_toggles() {
+        let mut recorder = Recorder::default();
+        recorder.record_bound_text("noclip\n");
+        recorder.record_bound_text("god;notarget\n");
+
+        // First frame, as in on_sv_frame_start().
+        recorder
+            .pending_console_commands
+            .push(recorder.pending_bound_commands.join(";"));
+        recorder.pending_bound_commands.clear();
+        let mut lines = vec![incomplete("cl_forwardspeed 400")];
+        assert_eq!(
+            fill_frame_bulks(
+                &mut lines,
+                &[0.01],
+                &[0.],
+                &recorder.pending_console_commands,
+            ),
+            Ok(true)
+        );
+        recorder.pending_console_commands.clear();
+
+        // Second frame, moving without any new commands.
+        recorder
+            .pending_console_commands
+            .push(recorder.pending_bound_commands.join(";"));
+        lines.push(incomplete("cl_forwardspeed 400"));
+        assert_eq!(
+            fill_frame_bulks(
+                &mut lines,
+                &[0.01],
+                &[0.],
+                &recorder.pending_console_commands,
+            ),
+            Ok(true)
+        );
+
+        assert_eq!(
+            lines,
+            [
+                complete("0.01", "noclip;god;notarget;cl_forwardspeed 400"),
+                complete("0.01", "cl_forwardspeed 400"),
+            ]
+        );
+    }
+
+    #[test]
+    fn extra_buttons() {
+        let extra_buttons = parse_extra_buttons("IN_ALT1; score=showscores").unwrap();
+        assert_eq!(
+            extra_buttons,
+            [
+                (Buttons::IN_ALT1, "alt1".to_owned()),
+                (Buttons::IN_SCORE, "showscores".to_owned())
+            ]
+        );
+        assert_eq!(parse_extra_buttons("alt2"), Err("alt2".to_owned()));
+
+        let alt1 = Buttons::IN_ALT1.bits();
+        let score = Buttons::IN_SCORE.bits();
+        let jump = Buttons::IN_JUMP.bits();
+        assert_eq!(
+            extra_button_commands(&extra_buttons, alt1 | jump, 0),
+            ["+alt1"]
+        );
+        assert!(extra_button_commands(&extra_buttons, alt1, alt1 | jump).is_empty());
+        assert_eq!(
+            extra_button_commands(&extra_buttons, score, alt1),
+            ["-alt1", "+showscores"]
+        );
+
+        // The bound commands of the extra buttons aren't recorded twice.
+        let mut recorder = Recorder {
+            extra_buttons,
+            ..Default::default()
+        };
+        recorder.record_bound_text("+alt1;slot1;-alt1\n");
+        assert_eq!(recorder.pending_bound_commands, ["slot1"]);
+    }
+
+    #[test]
+    fn cvar_snapshot() {
+        let names = parse_cvar_list("cl_forwardspeed;sv_maxspeed;missing");
+        let commands = cvar_snapshot_commands(&names, |name| match name {
+            "cl_forwardspeed" => Some("400".to_owned()),
+            "sv_maxspeed" => Some("320".to_owned()),
+            _ => None,
+        });
+        assert_eq!(commands, ["cl_forwardspeed \"400\"", "sv_maxspeed \"320\""]);
+    }
+
+    #[test]
+    fn onground_transitions() {
+        let mut recorder = Recorder::default();
+        recorder.update_onground(false);
+        assert!(!recorder.was_onground && !recorder.is_onground);
+
+        // Landing.
+        recorder.update_onground(true);
+        assert!(!recorder.was_onground && recorder.is_onground);
+
+        recorder.update_onground(true);
+        assert!(recorder.was_onground && recorder.is_onground);
+
+        // Leaving the ground.
+        recorder.update_onground(false);
+        assert!(recorder.was_onground && !recorder.is_onground);
+    }
+
+    #[test]
+    fn up_down_tracking() {
+        let mut recorder = Recorder::default();
+        recorder.record_bound_text("+moveup 32\n");
+        assert!(recorder.up_held);
+        recorder.record_bound_text("+movedown 33;-moveup 32\n");
+        assert!(!recorder.up_held);
+        assert!(recorder.down_held);
+        assert!(recorder.pending_bound_commands.is_empty());
+    }
+
+    const LGAGST_PARAMETERS: LgagstParameters = LgagstParameters {
+        frame_time: 0.01,
+        max_speed: 320.,
+        accelerate: 10.,
+        air_accelerate: 10.,
+        friction: 4.,
+        stop_speed: 100.,
+        ent_friction: 1.,
+    };
+
+    #[test]
+    fn lgagst_jumps() {
+        let p = LGAGST_PARAMETERS;
+        assert!(is_lgagst_jump(p, true, false, 500.));
+        assert!(!is_lgagst_jump(p, true, true, 500.));
+        assert!(!is_lgagst_jump(p, false, false, 500.));
+        assert!(!is_lgagst_jump(p, true, false, 10.));
+    }
+
+    #[test]
+    fn non_optimal_jumps_stay_jumps() {
+        // Below the max speed the ground strafing still accelerates faster than the air, so
+        // `lgagst` wouldn't jump yet.
+        assert!(!is_lgagst_jump(LGAGST_PARAMETERS, true, false, 300.));
+        assert!(!jump_is_optimal(LGAGST_PARAMETERS, 300.));
+
+        // Well above it, the friction loses more than the ground strafing gains.
+        assert!(jump_is_optimal(LGAGST_PARAMETERS, 500.));
+    }
+
+    #[test]
+    fn fire_comments() {
+        assert_eq!(
+            fire_comment(true, false, 42),
+            Some(Line::Comment(" FIRE @ tick 42".to_owned()))
+        );
+        // Holding and releasing the attack doesn't fire again.
+        assert_eq!(fire_comment(true, true, 43), None);
+        assert_eq!(fire_comment(false, true, 44), None);
+        assert_eq!(fire_comment(false, false, 45), None);
+    }
+
+    #[test]
+    fn water_levels() {
+        // Dry land doesn't produce anything.
+        assert_eq!(water_level_comment(0, 0), None);
+
+        // Jumping into deep water, then surfacing to waist level.
+        assert_eq!(
+            water_level_comment(0, 3),
+            Some(Line::Comment(" Water level: dry -> eyes".to_owned()))
+        );
+        assert_eq!(water_level_comment(3, 3), None);
+        assert_eq!(
+            water_level_comment(3, 2),
+            Some(Line::Comment(" Water level: eyes -> waist".to_owned()))
+        );
+    }
+
+    #[test]
+    fn tickrate_comments() {
+        assert_eq!(
+            tickrate_comment(Some("100".to_owned())),
+            Some(Line::Comment(" tickrate: 100".to_owned()))
+        );
+        assert_eq!(tickrate_comment(None), None);
+    }
+
+    #[test]
+    fn key_speeds_are_finite() {
+        for state in 0..8 {
+            let key = Key { state };
+            assert!(key.multiplier() > 0., "state {}", state);
+            assert!(key_speed(400., key, 400.).is_finite(), "state {}", state);
+            assert!(key_speed(-400., key, -400.).is_finite(), "state {}", state);
+        }
+
+        assert_eq!(divide_by_multiplier(400., 0., 300.), 300.);
+        assert_eq!(divide_by_multiplier(0., 0., 0.), 0.);
+        assert_eq!(divide_by_multiplier(400., 0.5, 400.), 800.);
+    }
+
+    #[test]
+    fn crouch_jumps() {
+        assert!(is_crouch_jump((true, true), (false, false)));
+        assert!(!is_crouch_jump((true, true), (true, false)));
+        assert!(!is_crouch_jump((true, true), (false, true)));
+        assert!(!is_crouch_jump((true, false), (false, false)));
+        assert!(!is_crouch_jump((false, true), (false, false)));
+    }
+
+    #[test]
+    fn ducktap() {
+        let mut tracker = DuckTracker::default();
+        let mut lines = Vec::new();
+        for (duck, on_ground_last) in [(false, true), (true, true), (false, true)] {
+            if tracker.update(duck, on_ground_last) {
+                convert_to_ducktap(&mut lines);
+            }
+
+            let mut frame_bulk = FrameBulk::with_frame_time("0.01".to_owned());
+            frame_bulk.action_keys.duck = duck;
+            lines.push(Line::FrameBulk(frame_bulk));
+        }
+
+        let mut expected = FrameBulk::with_frame_time("0.01".to_owned());
+        expected.auto_actions.leave_ground_action = Some(LeaveGroundAction {
+            speed: LeaveGroundActionSpeed::Any,
+            times: Times::Limited(NonZeroU32::new(1).unwrap()),
+            type_: LeaveGroundActionType::DuckTap { zero_ms: false },
+        });
+        assert_eq!(lines[1], Line::FrameBulk(expected));
+    }
+
+    #[test]
+    fn held_duck_is_not_ducktap() {
+        let mut tracker = DuckTracker::default();
+        assert!(!tracker.update(true, true));
+        assert!(!tracker.update(true, true));
+        assert!(!tracker.update(true, true));
+        assert!(!tracker.update(false, true));
+
+        // Pressed in the air.
+        assert!(!tracker.update(true, true));
+        assert!(!tracker.update(false, false));
+    }
+
+    #[test]
+    fn frametime_formatting() {
+        assert_eq!(format_frametime(0.001), "0.001");
+        assert_eq!(format_frametime(0.010000000000000002), "0.01");
+        assert_eq!(format_frametime(0.015625), "0.015625");
+        assert_eq!(format_frametime(1.), "1");
+        assert_eq!(format_frametime(0.), "0");
+    }
+
+    #[test]
+    fn utc_formatting() {
+        assert_eq!(format_utc(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_utc(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_utc(1700000000), "2023-11-14 22:13:20 UTC");
+    }
+
+    #[test]
+    fn part_filenames() {
+        let filename = Path::new("dir/run.hltas");
+        assert_eq!(part_filename(filename, 1), Path::new("dir/run.hltas"));
+        assert_eq!(part_filename(filename, 2), Path::new("dir/run_2.hltas"));
+        assert_eq!(part_filename(Path::new("run"), 3), Path::new("run_3"));
+    }
+
+    #[test]
+    fn speeds_reconciled_with_cvars() {
+        let cvar = |name: &str| match name {
+            "cl_sidespeed" => Some(100.),
+            "cl_forwardspeed" => Some(400.),
+            _ => None,
+        };
+
+        let mut commands = [
+            "cl_sidespeed 100.00000762939453".to_owned(),
+            "cl_forwardspeed 226.27416997969522".to_owned(),
+            "cl_upspeed 320.0000047683716".to_owned(),
+            "+speed".to_owned(),
+        ];
+        reconcile_speeds(&mut commands, cvar);
+
+        assert_eq!(
+            commands,
+            [
+                // Matches the capped `cl_sidespeed`.
+                "cl_sidespeed 100",
+                // Clamped by the max speed, so it's kept.
+                "cl_forwardspeed 226.27416997969522",
+                // Unknown variables are kept.
+                "cl_upspeed 320.0000047683716",
+                "+speed",
+            ]
+        );
+    }
+
+    #[test]
+    fn angle_rounding() {
+        let mut frame_bulk = FrameBulk::with_frame_time(String::new());
+        frame_bulk.auto_actions.movement = Some(AutoMovement::SetYaw(89.999985));
+        frame_bulk.pitch = Some(-12.345678);
+        let rounded = |decimals| {
+            let mut frame_bulk = frame_bulk.clone();
+            round_angles(&mut frame_bulk, decimals);
+            (frame_bulk.auto_actions.movement, frame_bulk.pitch)
+        };
+
+        assert_eq!(
+            rounded(None),
+            (Some(AutoMovement::SetYaw(89.999985)), Some(-12.345678))
+        );
+        assert_eq!(
+            rounded(Some(5)),
+            (Some(AutoMovement::SetYaw(89.99998)), Some(-12.34568))
+        );
+        assert_eq!(
+            rounded(Some(2)),
+            (Some(AutoMovement::SetYaw(90.)), Some(-12.35))
+        );
+        assert_eq!(
+            rounded(Some(0)),
+            (Some(AutoMovement::SetYaw(90.)), Some(-12.))
+        );
+    }
+
+    #[test]
+    fn origin_divergence() {
+        assert!(!origins_diverged([1., 2., 3.], [1., 2., 3.]));
+        assert!(!origins_diverged([1., 2., 3.], [1.005, 2., 3.]));
+        assert!(origins_diverged([1., 2., 3.], [1., 2.5, 3.]));
+    }
+
+    #[test]
+    fn angle_divergence() {
+        assert!(!angles_diverged([10., 90., 0.], [10., 90., 0.]));
+        assert!(!angles_diverged([0., 359.995, 0.], [0., -0.002, 0.]));
+        assert!(angles_diverged([10., 90., 0.], [10., 90.5, 0.]));
+        assert!(angles_diverged([10., 90., 0.], [-10., 90., 0.]));
+    }
+
+    #[test]
+    fn hud_texts() {
+        assert_eq!(
+            hud_text(Path::new("tas").join("run.hltas").as_path(), 1234),
+            "* REC run.hltas 1234"
+        );
+        assert_eq!(hud_text(Path::new("run.hltas"), 0), "* REC run.hltas 0");
+    }
+
+    #[test]
+    fn engine_comments() {
+        assert_eq!(
+            engine_comment(Some(8684)),
+            Line::Comment(" engine: build 8684".to_owned())
+        );
+        assert_eq!(
+            engine_comment(None),
+            Line::Comment(" engine: unknown".to_owned())
+        );
+    }
+
+    #[test]
+    fn player_state() {
+        assert_eq!(
+            format_player_state([-128., 512.03125, 36.], [0., -250.5, 0.]),
+            " Starting origin: -128.000 512.031 36.000, velocity: 0.000 -250.500 0.000"
+        );
+    }
+
+    #[test]
+    fn cvar_lists() {
+        assert_eq!(parse_cvar_list("fov;sensitivity"), ["fov", "sensitivity"]);
+        assert_eq!(
+            parse_cvar_list(" fov ; ;sensitivity;"),
+            ["fov", "sensitivity"]
+        );
+        assert!(parse_cvar_list("").is_empty());
+    }
+
+    #[test]
+    fn autojump() {
+        let mut frame_bulk = FrameBulk::with_frame_time(String::new());
+        frame_bulk.action_keys.jump = true;
+        apply_autojump(&mut frame_bulk);
+        assert!(!frame_bulk.action_keys.jump);
+        assert_eq!(
+            frame_bulk.auto_actions.leave_ground_action,
+            Some(LeaveGroundAction {
+                speed: LeaveGroundActionSpeed::Any,
+                times: Times::UnlimitedWithinFrameBulk,
+                type_: LeaveGroundActionType::Jump,
+            })
+        );
+
+        // A detected LGAGST stays.
+        let lgagst = LeaveGroundAction {
+            speed: LeaveGroundActionSpeed::Optimal,
+            times: Times::Limited(NonZeroU32::new(1).unwrap()),
+            type_: LeaveGroundActionType::Jump,
+        };
+        let mut frame_bulk = FrameBulk::with_frame_time(String::new());
+        frame_bulk.auto_actions.leave_ground_action = Some(lgagst);
+        apply_autojump(&mut frame_bulk);
+        assert_eq!(frame_bulk.auto_actions.leave_ground_action, Some(lgagst));
+    }
+
+    #[test]
+    fn crlf_writer() {
+        let mut writer = CrlfWriter(Vec::new());
+        io::Write::write_all(&mut writer, b"version 1\nframes\n").unwrap();
+        io::Write::write_all(&mut writer, b"\n----------|------|------|0.01|-|-|1\n").unwrap();
+
+        assert_eq!(
+            writer.0,
+            b"version 1\r\nframes\r\n\r\n----------|------|------|0.01|-|-|1\r\n"
+        );
+    }
+
+    #[test]
+    fn appended_extensions() {
+        assert_eq!(
+            with_appended_extension(Path::new("dir/run.hltas"), "tmp"),
+            Path::new("dir/run.hltas.tmp")
+        );
+        assert_eq!(
+            with_appended_extension(Path::new("run"), "tmp"),
+            Path::new("run.tmp")
+        );
+    }
+
+    #[test]
+    fn autosave_filenames() {
+        assert_eq!(
+            autosave_filename(Path::new("dir/run.hltas")),
+            Path::new("dir/run.hltas.autosave")
+        );
+    }
+
+    #[test]
+    fn discard_without_writing() {
+        let filename =
+            std::env::temp_dir().join(format!("bxt-rs-discard-test-{}.hltas", std::process::id()));
+        let autosave = autosave_filename(&filename);
+        fs::write(&autosave, "").unwrap();
+
+        let path = filename.with_extension("origins");
+        let mut origins = OriginsSink::create(path.clone()).unwrap();
+        origins
+            .on_tick(
+                1,
+                Some(PlayerPosition {
+                    origin: [0., 0., 0.],
+                    angles: [0., 0., 0.],
+                }),
+            )
+            .unwrap();
+
+        discard(Recorder {
+            filename: filename.clone(),
+            sinks: vec![Box::new(origins)],
+            ..Default::default()
+        });
+
+        assert!(!filename.exists());
+        assert!(!autosave.exists());
+        assert!(!path.exists());
+    }
+
+    #[test]
+    fn path_resolution() {
+        // Absolute on every platform.
+        let root = std::env::temp_dir();
+        let home = root.join("home");
+        let dir = root.join("tas");
+        let dir_str = dir.to_str().unwrap();
+        let home = Some(home.as_path());
+
+        assert_eq!(
+            resolve_path("", Path::new("run.hltas"), home),
+            Path::new("run.hltas")
+        );
+        assert_eq!(
+            resolve_path(dir_str, Path::new("run.hltas"), home),
+            dir.join("run.hltas")
+        );
+        assert_eq!(
+            resolve_path("~/tas", Path::new("run.hltas"), home),
+            root.join("home").join("tas").join("run.hltas")
+        );
+        assert_eq!(
+            resolve_path(dir_str, Path::new("~/run.hltas"), home),
+            root.join("home").join("run.hltas")
+        );
+        assert_eq!(
+            resolve_path(dir_str, &root.join("run.hltas"), home),
+            root.join("run.hltas")
+        );
+        assert_eq!(
+            resolve_path("", Path::new("~/run.hltas"), None),
+            Path::new("~/run.hltas")
+        );
+    }
+
+    #[test]
+    fn forward_and_back_held() {
+        let mut keys = Keys::default();
+        let both = MovementKeys {
+            forward: true,
+            back: true,
+            ..Default::default()
+        };
+
+        // Both pressed on this frame.
+        let (movement_keys, commands) = record(&mut keys, cmd(100., 0., 0.), both);
+        assert_eq!(movement_keys, both);
+        assert_eq!(commands, "cl_forwardspeed 200;cl_backspeed 0");
+
+        let (movement_keys, commands) = record(&mut keys, cmd(200., 0., 0.), both);
+        assert_eq!(movement_keys, both);
+        assert_eq!(commands, "cl_forwardspeed 200;cl_backspeed 0");
+
+        let (movement_keys, commands) = record(&mut keys, cmd(-100., 0., 0.), both);
+        assert_eq!(movement_keys, both);
+        assert_eq!(commands, "cl_forwardspeed 0;cl_backspeed 100");
+    }
+
+    #[test]
+    fn left_and_right_held() {
+        let mut keys = Keys::default();
+        let left = MovementKeys {
+            left: true,
+            ..Default::default()
+        };
+        let both = MovementKeys {
+            left: true,
+            right: true,
+            ..Default::default()
+        };
+
+        record(&mut keys, cmd(0., -400., 0.), left);
+
+        // Right is pressed on this frame, so it has half the multiplier of left.
+        let (movement_keys, commands) = record(&mut keys, cmd(0., -200., 0.), both);
+        assert_eq!(movement_keys, both);
+        assert_eq!(commands, "cl_sidespeed 400");
+
+        // Held for a while, the keys cancel out.
+        let (movement_keys, commands) = record(&mut keys, cmd(0., 0., 0.), both);
+        assert_eq!(movement_keys, both);
+        assert_eq!(commands, "");
+
+        // Can't be reproduced with both keys.
+        let mut movement_keys = both;
+        let moves = record_movement(&mut keys, &cmd(0., 100., 0.), &mut movement_keys, 1.);
+        assert!(moves.approximated);
+        assert!(movement_keys.right && !movement_keys.left);
+        assert_eq!(moves.commands, ["cl_sidespeed 100"]);
+    }
+}
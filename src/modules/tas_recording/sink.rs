@@ -0,0 +1,261 @@
+//! Additional outputs of a recording, written alongside or instead of the script.
+
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use super::binary::{self, Record};
+use crate::ffi::usercmd::usercmd_s;
+
+/// Position of the player at the start of a tick.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PlayerPosition {
+    pub origin: [f32; 3],
+    /// View angles, pitch, yaw and roll.
+    pub angles: [f32; 3],
+}
+
+/// Output of a recording which consumes the recorded data frame by frame.
+pub trait RecordingSink {
+    /// Returns the path of the output file, for error messages.
+    fn path(&self) -> &Path;
+
+    /// Called on every tick with the player position, if there's a player.
+    fn on_tick(&mut self, _tick: u64, _player: Option<PlayerPosition>) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called on every command run by the server, including every command of a split frame.
+    fn on_cmd(&mut self, _tick: u64, _cmd: &usercmd_s) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Called at the end of every physics frame with the command record of the frame.
+    fn on_frame(&mut self, _record: &Record) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Writes out any buffered data at the end of the recording.
+    fn finish(&mut self) -> io::Result<()>;
+
+    /// Throws away any buffered data and removes the output file.
+    fn discard(self: Box<Self>);
+}
+
+/// Closes the writer without flushing it and removes the output file.
+fn remove_output(path: &Path, writer: BufWriter<File>) {
+    drop(writer.into_parts());
+
+    // Nothing to do if the file is already gone.
+    let _ = fs::remove_file(path);
+}
+
+/// Writes the command records in the binary format, see [`binary`].
+pub struct BinarySink {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl BinarySink {
+    /// Creates the output file and writes the header.
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        binary::write_header(&mut writer)?;
+        Ok(Self { path, writer })
+    }
+}
+
+impl RecordingSink for BinarySink {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn on_frame(&mut self, record: &Record) -> io::Result<()> {
+        record.write(&mut self.writer)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn discard(self: Box<Self>) {
+        remove_output(&self.path, self.writer);
+    }
+}
+
+/// Writes the player origin and view angles on every tick as `tick x y z pitch yaw roll` lines.
+pub struct OriginsSink {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl OriginsSink {
+    /// Creates the output file.
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        let writer = BufWriter::new(File::create(&path)?);
+        Ok(Self { path, writer })
+    }
+}
+
+impl RecordingSink for OriginsSink {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn on_tick(&mut self, tick: u64, player: Option<PlayerPosition>) -> io::Result<()> {
+        // Frames without a player are skipped.
+        match player {
+            Some(PlayerPosition {
+                origin: [x, y, z],
+                angles: [pitch, yaw, roll],
+            }) => writeln!(
+                self.writer,
+                "{} {} {} {} {} {} {}",
+                tick, x, y, z, pitch, yaw, roll
+            ),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn discard(self: Box<Self>) {
+        remove_output(&self.path, self.writer);
+    }
+}
+
+/// Writes every field of every command as CSV, with a header row.
+pub struct UsercmdsSink {
+    path: PathBuf,
+    writer: BufWriter<File>,
+}
+
+impl UsercmdsSink {
+    /// Creates the output file and writes the header row.
+    pub fn create(path: PathBuf) -> io::Result<Self> {
+        let mut writer = BufWriter::new(File::create(&path)?);
+        writeln!(
+            writer,
+            "tick,lerp_msec,msec,pitch,yaw,roll,forwardmove,sidemove,upmove,lightlevel,buttons,\
+             impulse,weaponselect,impact_index,impact_x,impact_y,impact_z"
+        )?;
+        Ok(Self { path, writer })
+    }
+}
+
+impl RecordingSink for UsercmdsSink {
+    fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn on_cmd(&mut self, tick: u64, cmd: &usercmd_s) -> io::Result<()> {
+        let [pitch, yaw, roll] = cmd.viewangles;
+        let [impact_x, impact_y, impact_z] = cmd.impact_position;
+        writeln!(
+            self.writer,
+            "{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+            tick,
+            cmd.lerp_msec,
+            cmd.msec,
+            pitch,
+            yaw,
+            roll,
+            cmd.forwardmove,
+            cmd.sidemove,
+            cmd.upmove,
+            cmd.lightlevel,
+            cmd.buttons,
+            cmd.impulse,
+            cmd.weaponselect,
+            cmd.impact_index,
+            impact_x,
+            impact_y,
+            impact_z,
+        )
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+
+    fn discard(self: Box<Self>) {
+        remove_output(&self.path, self.writer);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn origins_sink() {
+        let path = std::env::temp_dir().join("bxt-rs-origins-sink-test.origins");
+        let mut sink = OriginsSink::create(path.clone()).unwrap();
+        sink.on_tick(
+            1,
+            Some(PlayerPosition {
+                origin: [0., 16.5, -36.],
+                angles: [10., 90., 0.],
+            }),
+        )
+        .unwrap();
+        sink.on_tick(2, None).unwrap();
+        sink.on_tick(
+            3,
+            Some(PlayerPosition {
+                origin: [1., 2., 3.],
+                angles: [0., -45.5, 0.],
+            }),
+        )
+        .unwrap();
+        sink.finish().unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "1 0 16.5 -36 10 90 0\n3 1 2 3 0 -45.5 0\n"
+        );
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn usercmds_sink() {
+        let path = std::env::temp_dir().join("bxt-rs-usercmds-sink-test.usercmds");
+        let mut sink = UsercmdsSink::create(path.clone()).unwrap();
+        let cmd = usercmd_s {
+            lerp_msec: 100,
+            msec: 10,
+            viewangles: [-5., 90.5, 0.],
+            forwardmove: 400.,
+            sidemove: -200.,
+            upmove: 0.,
+            lightlevel: 42,
+            buttons: 2,
+            impulse: 0,
+            weaponselect: 0,
+            impact_index: 0,
+            impact_position: [0., 0., 0.],
+        };
+        sink.on_cmd(7, &cmd).unwrap();
+        sink.finish().unwrap();
+
+        let contents = fs::read_to_string(&path).unwrap();
+        let mut lines = contents.lines();
+        assert_eq!(
+            lines.next(),
+            Some(
+                "tick,lerp_msec,msec,pitch,yaw,roll,forwardmove,sidemove,upmove,lightlevel,buttons,\
+                 impulse,weaponselect,impact_index,impact_x,impact_y,impact_z"
+            )
+        );
+        assert_eq!(
+            lines.next(),
+            Some("7,100,10,-5,90.5,0,400,-200,0,42,2,0,0,0,0,0,0")
+        );
+        assert_eq!(lines.next(), None);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,638 @@
+//! Transformations of the recorded script.
+
+use std::num::NonZeroU32;
+
+use hltas::types::{
+    ActionKeys, AutoActions, AutoMovement, Change, ChangeTarget, FrameBulk, Line, MovementKeys,
+};
+
+use super::{
+    BXT_TAS_RECORDING_DETECT_CHANGE, BXT_TAS_RECORDING_OMIT_REPEAT_YAW,
+    BXT_TAS_RECORDING_RELATIVE_YAW,
+};
+use crate::utils::*;
+
+/// Joins consecutive frame bulks which differ only in the frame count.
+///
+/// Frame bulks with console commands are never joined as the commands run only once per frame
+/// bulk.
+pub(super) fn join_frame_bulks(lines: &mut Vec<Line>) {
+    let mut joined: Vec<Line> = Vec::with_capacity(lines.len());
+
+    for line in lines.drain(..) {
+        if let (Some(Line::FrameBulk(last)), Line::FrameBulk(frame_bulk)) =
+            (joined.last_mut(), &line)
+        {
+            if let Some(frame_count) = joined_frame_count(last, frame_bulk) {
+                last.frame_count = frame_count;
+                continue;
+            }
+        }
+
+        joined.push(line);
+    }
+
+    *lines = joined;
+}
+
+/// Minimum number of frame bulks in a yaw sweep to collapse it into a `change` line.
+const MIN_SWEEP_FRAME_BULKS: usize = 4;
+
+/// Maximum difference between the yaw changes of the frames of a sweep, in degrees.
+const SWEEP_TOLERANCE: f32 = 0.001;
+
+/// Collapses yaw sweeps into `change` lines.
+///
+/// A sweep is a run of single-frame bulks with the same frame time where the yaw changes by the
+/// same amount every frame. The first frame bulk keeps its yaw, and the rest of them have their
+/// yaw set by a `change` line over their total frame time. Jittery sweeps aren't collapsed.
+fn collapse_yaw_sweeps(lines: &mut Vec<Line>) {
+    let mut collapsed = Vec::with_capacity(lines.len());
+
+    let mut i = 0;
+    while i < lines.len() {
+        let length = sweep_length(&lines[i..]);
+        if length < MIN_SWEEP_FRAME_BULKS {
+            collapsed.push(lines[i].clone());
+            i += 1;
+            continue;
+        }
+
+        let (first_yaw, frame_time) = sweep_frame_bulk(&lines[i]).unwrap();
+        let (second_yaw, _) = sweep_frame_bulk(&lines[i + 1]).unwrap();
+        let changed_frames = (length - 1) as f32;
+
+        collapsed.push(lines[i].clone());
+        collapsed.push(Line::Change(Change {
+            target: ChangeTarget::Yaw,
+            final_value: first_yaw + yaw_difference(first_yaw, second_yaw) * changed_frames,
+            over: frame_time * changed_frames,
+        }));
+        for line in &lines[i + 1..i + length] {
+            let mut line = line.clone();
+            if let Line::FrameBulk(frame_bulk) = &mut line {
+                frame_bulk.auto_actions.movement = None;
+            }
+            collapsed.push(line);
+        }
+
+        i += length;
+    }
+
+    *lines = collapsed;
+}
+
+/// Removes the yaw from frame bulks which set the same yaw as the previous frame bulk.
+///
+/// Frame bulks without a yaw keep the view yaw as is, so this doesn't change the replay. Lines other
+/// than frame bulks and comments, like loads or `change` lines, can change the yaw, so the frame
+/// bulk after them always keeps its yaw.
+fn omit_repeated_yaws(lines: &mut [Line]) {
+    let mut last_yaw = None;
+    for line in lines {
+        let frame_bulk = match line {
+            Line::FrameBulk(frame_bulk) => frame_bulk,
+            Line::Comment(_) => continue,
+            _ => {
+                last_yaw = None;
+                continue;
+            }
+        };
+
+        match frame_bulk.auto_actions.movement {
+            Some(AutoMovement::SetYaw(yaw)) => {
+                if last_yaw == Some(yaw) {
+                    frame_bulk.auto_actions.movement = None;
+                }
+                last_yaw = Some(yaw);
+            }
+            // Strafing changes the yaw on its own.
+            Some(_) => last_yaw = None,
+            None => (),
+        }
+    }
+}
+
+/// Returns the number of frame bulks in the yaw sweep at the start of `lines`.
+fn sweep_length(lines: &[Line]) -> usize {
+    let mut frame_bulks = lines.iter().map_while(|line| match line {
+        Line::FrameBulk(frame_bulk) => Some((frame_bulk, sweep_frame_bulk(line)?)),
+        _ => None,
+    });
+
+    let (first, (first_yaw, _)) = match frame_bulks.next() {
+        Some(x) => x,
+        None => return 0,
+    };
+    let (second, (second_yaw, _)) = match frame_bulks.next() {
+        Some(x) if x.0.frame_time == first.frame_time => x,
+        _ => return 1,
+    };
+
+    let change = yaw_difference(first_yaw, second_yaw);
+    if change == 0. {
+        return 1;
+    }
+
+    let mut length = 2;
+    let mut last_yaw = second_yaw;
+    for (frame_bulk, (yaw, _)) in frame_bulks {
+        if frame_bulk.frame_time != second.frame_time
+            || (yaw_difference(last_yaw, yaw) - change).abs() > SWEEP_TOLERANCE
+        {
+            break;
+        }
+
+        length += 1;
+        last_yaw = yaw;
+    }
+
+    length
+}
+
+/// Returns the yaw and the frame time of the frame bulk, if it can be a part of a yaw sweep.
+fn sweep_frame_bulk(line: &Line) -> Option<(f32, f32)> {
+    let frame_bulk = match line {
+        Line::FrameBulk(frame_bulk) => frame_bulk,
+        _ => return None,
+    };
+
+    if frame_bulk.frame_count.get() != 1
+        || matches!(&frame_bulk.console_command, Some(command) if !command.is_empty())
+    {
+        return None;
+    }
+
+    let yaw = match frame_bulk.auto_actions.movement {
+        Some(AutoMovement::SetYaw(yaw)) => yaw,
+        _ => return None,
+    };
+
+    Some((yaw, frame_bulk.frame_time.parse().ok()?))
+}
+
+/// Returns the difference from yaw `a` to yaw `b`, normalized into `[-180; 180)`.
+fn yaw_difference(a: f32, b: f32) -> f32 {
+    (b - a + 180.).rem_euclid(360.) - 180.
+}
+
+/// Returns the frame count of the two frame bulks joined together, if they can be joined.
+///
+/// Only frame bulks that are the same apart from the frame count can be joined, so any button or
+/// key change, like releasing `+use`, always starts a new frame bulk.
+fn joined_frame_count(a: &FrameBulk, b: &FrameBulk) -> Option<NonZeroU32> {
+    let has_commands = |frame_bulk: &FrameBulk| matches!(&frame_bulk.console_command, Some(command) if !command.is_empty());
+    if has_commands(a) || has_commands(b) {
+        return None;
+    }
+
+    let frame_count = NonZeroU32::new(a.frame_count.get().checked_add(b.frame_count.get())?)?;
+    let a = FrameBulk {
+        frame_count: b.frame_count,
+        ..a.clone()
+    };
+    if a == *b {
+        Some(frame_count)
+    } else {
+        None
+    }
+}
+
+/// Applies the transforms enabled by the console variables to the recorded lines.
+pub(super) fn transform_lines(marker: MainThreadMarker, lines: &mut Vec<Line>) {
+    if BXT_TAS_RECORDING_RELATIVE_YAW.as_bool(marker) {
+        // Before collapsing, which only knows how to rotate the frame bulks.
+        make_yaws_relative(lines);
+    }
+    if BXT_TAS_RECORDING_DETECT_CHANGE.as_bool(marker) {
+        // Before joining, so that the frame bulks of the sweep can be joined.
+        collapse_yaw_sweeps(lines);
+    }
+    if BXT_TAS_RECORDING_OMIT_REPEAT_YAW.as_bool(marker) {
+        // Before joining, so that the frame bulks without the yaw can be joined.
+        omit_repeated_yaws(lines);
+    }
+    join_frame_bulks(lines);
+}
+
+/// Removes the trailing frame bulks which are still waiting for their frame times.
+pub(super) fn drop_incomplete_frame_bulks(lines: &mut Vec<Line>) {
+    while matches!(lines.last(), Some(Line::FrameBulk(frame_bulk)) if frame_bulk.frame_time.is_empty())
+    {
+        lines.pop();
+    }
+}
+
+/// Returns whether the frame bulk doesn't press anything or run any console commands.
+///
+/// The view angles don't count, since they don't do anything on their own.
+fn is_idle_frame_bulk(frame_bulk: &FrameBulk) -> bool {
+    let auto_actions = AutoActions {
+        movement: None,
+        ..frame_bulk.auto_actions
+    };
+
+    matches!(
+        frame_bulk.auto_actions.movement,
+        None | Some(AutoMovement::SetYaw(_))
+    ) && auto_actions == AutoActions::default()
+        && frame_bulk.movement_keys == MovementKeys::default()
+        && frame_bulk.action_keys == ActionKeys::default()
+        && frame_bulk
+            .console_command
+            .as_deref()
+            .unwrap_or("")
+            .is_empty()
+}
+
+/// Removes the idle frame bulks up to the first one that isn't idle, returning the number of
+/// removed frames.
+///
+/// Other lines, like comments, are kept. Frame bulks that are still waiting for their frame time
+/// are never removed.
+pub(super) fn trim_idle_start(lines: &mut Vec<Line>) -> u64 {
+    let mut trimmed = 0;
+    let mut i = 0;
+    while i < lines.len() {
+        match &lines[i] {
+            Line::FrameBulk(frame_bulk)
+                if !frame_bulk.frame_time.is_empty() && is_idle_frame_bulk(frame_bulk) =>
+            {
+                trimmed += u64::from(frame_bulk.frame_count.get());
+                lines.remove(i);
+            }
+            Line::FrameBulk(_) => break,
+            _ => i += 1,
+        }
+    }
+
+    trimmed
+}
+
+/// Adds `degrees` to the yaw of every frame bulk, wrapping into `[0; 360)`.
+///
+/// The yaw is changed both in `SetYaw` and in the `bxt_set_angles` angle anchors. Returns the
+/// number of frame bulks with a changed yaw.
+pub(super) fn rotate_yaws(lines: &mut [Line], degrees: f32) -> usize {
+    let rotate = |yaw: f32| (yaw + degrees).rem_euclid(360.);
+
+    let mut rotated = 0;
+    for line in lines {
+        let frame_bulk = match line {
+            Line::FrameBulk(frame_bulk) => frame_bulk,
+            _ => continue,
+        };
+
+        let mut changed = false;
+        if let Some(AutoMovement::SetYaw(yaw)) = &mut frame_bulk.auto_actions.movement {
+            *yaw = rotate(*yaw);
+            changed = true;
+        }
+
+        if let Some(command) = &mut frame_bulk.console_command {
+            let commands: Vec<String> = command
+                .split(';')
+                .map(|command| {
+                    let mut args = command.split(' ');
+                    if args.next() != Some("bxt_set_angles") {
+                        return command.to_owned();
+                    }
+
+                    match (args.next(), args.next().and_then(|yaw| yaw.parse().ok())) {
+                        (Some(pitch), Some(yaw)) if args.next().is_none() => {
+                            changed = true;
+                            format!("bxt_set_angles {} {}", pitch, rotate(yaw))
+                        }
+                        _ => command.to_owned(),
+                    }
+                })
+                .collect();
+            *command = commands.join(";");
+        }
+
+        if changed {
+            rotated += 1;
+        }
+    }
+
+    rotated
+}
+
+/// Makes the yaws relative to the yaw of the first frame bulk and adds a comment with the basis at
+/// the start.
+fn make_yaws_relative(lines: &mut Vec<Line>) {
+    let basis = lines.iter().find_map(|line| match line {
+        Line::FrameBulk(FrameBulk {
+            auto_actions:
+                AutoActions {
+                    movement: Some(AutoMovement::SetYaw(yaw)),
+                    ..
+                },
+            ..
+        }) => Some(*yaw),
+        _ => None,
+    });
+
+    if let Some(basis) = basis {
+        rotate_yaws(lines, -basis);
+        lines.insert(0, Line::Comment(format!(" Yaw basis: {}", basis)));
+    }
+}
+
+/// Removes the last `count` frame bulks, keeping the other lines.
+///
+/// Returns the removed frame bulks in order, fewer than `count` if there weren't enough.
+pub(super) fn remove_frame_bulks(lines: &mut Vec<Line>, count: usize) -> Vec<FrameBulk> {
+    let mut removed = Vec::new();
+    let mut index = lines.len();
+    while removed.len() < count && index > 0 {
+        index -= 1;
+        if matches!(lines[index], Line::FrameBulk(_)) {
+            match lines.remove(index) {
+                Line::FrameBulk(frame_bulk) => removed.push(frame_bulk),
+                _ => unreachable!(),
+            }
+        }
+    }
+
+    removed.reverse();
+    removed
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+
+    use hltas::types::{AutoMovement, Change, ChangeTarget, FrameBulk, Line, StrafeSettings};
+
+    use super::super::tests::incomplete;
+    use super::*;
+
+    fn frame_bulk(frame_time: &str, console_command: Option<&str>) -> Line {
+        Line::FrameBulk(FrameBulk {
+            console_command: console_command.map(str::to_owned),
+            ..FrameBulk::with_frame_time(frame_time.to_owned())
+        })
+    }
+
+    #[test]
+    fn join_identical_frame_bulks() {
+        let mut lines = vec![frame_bulk("0.01", None); 10];
+        join_frame_bulks(&mut lines);
+
+        let mut expected = FrameBulk::with_frame_time("0.01".to_owned());
+        expected.frame_count = NonZeroU32::new(10).unwrap();
+        assert_eq!(lines, [Line::FrameBulk(expected)]);
+    }
+
+    #[test]
+    fn dont_join_different_frame_bulks() {
+        let original = vec![
+            frame_bulk("0.01", None),
+            frame_bulk("0.02", None),
+            frame_bulk("0.02", Some("echo")),
+            frame_bulk("0.02", Some("echo")),
+            Line::Comment(" Paused".to_owned()),
+            frame_bulk("0.02", None),
+        ];
+        let mut lines = original.clone();
+        join_frame_bulks(&mut lines);
+        assert_eq!(lines, original);
+    }
+
+    fn with_yaw(yaw: f32) -> Line {
+        let mut frame_bulk = FrameBulk::with_frame_time("0.01".to_owned());
+        frame_bulk.auto_actions.movement = Some(AutoMovement::SetYaw(yaw));
+        Line::FrameBulk(frame_bulk)
+    }
+
+    #[test]
+    fn omit_constant_yaw() {
+        let mut lines = vec![
+            with_yaw(90.),
+            with_yaw(90.),
+            with_yaw(90.),
+            with_yaw(45.),
+            with_yaw(45.),
+        ];
+        omit_repeated_yaws(&mut lines);
+
+        let without_yaw = Line::FrameBulk(FrameBulk::with_frame_time("0.01".to_owned()));
+        assert_eq!(
+            lines,
+            [
+                with_yaw(90.),
+                without_yaw.clone(),
+                without_yaw.clone(),
+                with_yaw(45.),
+                without_yaw.clone(),
+            ]
+        );
+
+        // The constant frames are joined into one.
+        join_frame_bulks(&mut lines);
+        assert_eq!(lines.len(), 4);
+
+        // A load in between can change the yaw.
+        let mut lines = vec![with_yaw(90.), Line::SharedSeed(1), with_yaw(90.)];
+        let original = lines.clone();
+        omit_repeated_yaws(&mut lines);
+        assert_eq!(lines, original);
+    }
+
+    #[test]
+    fn collapse_linear_yaw_sweep() {
+        let mut lines: Vec<Line> = (0..10).map(|i| with_yaw(10. + 2. * i as f32)).collect();
+        lines.push(with_yaw(0.));
+        collapse_yaw_sweeps(&mut lines);
+
+        let mut expected = vec![
+            with_yaw(10.),
+            Line::Change(Change {
+                target: ChangeTarget::Yaw,
+                final_value: 28.,
+                over: 0.01 * 9.,
+            }),
+        ];
+        expected.extend(vec![
+            Line::FrameBulk(FrameBulk::with_frame_time(
+                "0.01".to_owned()
+            ));
+            9
+        ]);
+        expected.push(with_yaw(0.));
+        assert_eq!(lines, expected);
+
+        join_frame_bulks(&mut lines);
+        assert_eq!(lines.len(), 4);
+    }
+
+    #[test]
+    fn collapse_yaw_sweep_across_zero() {
+        let mut lines: Vec<Line> = [355., 358., 1., 4., 7.].into_iter().map(with_yaw).collect();
+        collapse_yaw_sweeps(&mut lines);
+
+        assert_eq!(
+            lines[1],
+            Line::Change(Change {
+                target: ChangeTarget::Yaw,
+                final_value: 367.,
+                over: 0.01 * 4.,
+            })
+        );
+    }
+
+    #[test]
+    fn dont_collapse_jittery_yaw_sweep() {
+        let original: Vec<Line> = [10., 12., 14.5, 16., 18., 18., 18., 18.]
+            .into_iter()
+            .map(with_yaw)
+            .collect();
+        let mut lines = original.clone();
+        collapse_yaw_sweeps(&mut lines);
+        assert_eq!(lines, original);
+    }
+
+    #[test]
+    fn relative_yaws() {
+        let mut lines = vec![with_yaw(90.), with_yaw(100.), with_yaw(45.)];
+        make_yaws_relative(&mut lines);
+        assert_eq!(
+            lines,
+            [
+                Line::Comment(" Yaw basis: 90".to_owned()),
+                with_yaw(0.),
+                with_yaw(10.),
+                with_yaw(315.),
+            ]
+        );
+
+        // Adding the basis back gives the original yaws.
+        rotate_yaws(&mut lines, 90.);
+        assert_eq!(lines[1..], [with_yaw(90.), with_yaw(100.), with_yaw(45.)]);
+
+        let mut lines = vec![incomplete("")];
+        make_yaws_relative(&mut lines);
+        assert_eq!(lines, [incomplete("")]);
+    }
+
+    #[test]
+    fn join_frame_bulks_with_empty_commands() {
+        let mut lines = vec![frame_bulk("0.01", Some("")), frame_bulk("0.01", Some(""))];
+        join_frame_bulks(&mut lines);
+        assert_eq!(lines.len(), 1);
+    }
+
+    #[test]
+    fn trim_start() {
+        let idle = |yaw| {
+            let mut frame_bulk = FrameBulk::with_frame_time("0.01".to_owned());
+            frame_bulk.auto_actions.movement = Some(AutoMovement::SetYaw(yaw));
+            frame_bulk.console_command = Some(String::new());
+            Line::FrameBulk(frame_bulk)
+        };
+        let mut forward = FrameBulk::with_frame_time("0.01".to_owned());
+        forward.movement_keys.forward = true;
+        let forward = Line::FrameBulk(forward);
+
+        let mut lines = vec![
+            Line::Comment(" start".to_owned()),
+            idle(0.),
+            idle(10.),
+            frame_bulk("0.01", None),
+            forward.clone(),
+            idle(0.),
+        ];
+        assert_eq!(trim_idle_start(&mut lines), 3);
+        assert_eq!(
+            lines,
+            [Line::Comment(" start".to_owned()), forward, idle(0.)]
+        );
+
+        // Console commands and frame bulks waiting for their frame time stop the trimming.
+        let mut lines = vec![frame_bulk("0.01", Some("bxt_set_angles 0 0")), idle(0.)];
+        assert_eq!(trim_idle_start(&mut lines), 0);
+        assert_eq!(lines.len(), 2);
+        let mut lines = vec![incomplete(""), idle(0.)];
+        assert_eq!(trim_idle_start(&mut lines), 0);
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn drop_incomplete() {
+        let complete = FrameBulk::with_frame_time("0.01".to_owned());
+        let incomplete = FrameBulk::with_frame_time(String::new());
+        let mut lines = vec![
+            Line::FrameBulk(complete.clone()),
+            Line::Comment(" comment".to_owned()),
+            Line::FrameBulk(incomplete.clone()),
+            Line::FrameBulk(incomplete),
+        ];
+
+        drop_incomplete_frame_bulks(&mut lines);
+
+        assert_eq!(
+            lines,
+            [
+                Line::FrameBulk(complete),
+                Line::Comment(" comment".to_owned())
+            ]
+        );
+    }
+
+    #[test]
+    fn remove_last_frame_bulks() {
+        let frame_bulk = Line::FrameBulk(FrameBulk::with_frame_time("0.01".to_owned()));
+        let comment = Line::Comment(" comment".to_owned());
+        let mut lines = vec![
+            comment.clone(),
+            frame_bulk.clone(),
+            frame_bulk.clone(),
+            comment.clone(),
+            frame_bulk.clone(),
+        ];
+
+        assert_eq!(remove_frame_bulks(&mut lines, 2).len(), 2);
+        assert_eq!(lines, [comment.clone(), frame_bulk, comment.clone()]);
+
+        assert_eq!(remove_frame_bulks(&mut lines, 5).len(), 1);
+        assert_eq!(lines, [comment.clone(), comment]);
+    }
+
+    #[test]
+    fn rotate() {
+        let with_movement = |movement, console_command: &str| {
+            let mut frame_bulk = FrameBulk::with_frame_time("0.01".to_owned());
+            frame_bulk.auto_actions.movement = Some(movement);
+            frame_bulk.console_command = Some(console_command.to_owned());
+            Line::FrameBulk(frame_bulk)
+        };
+        let yaw = |yaw, console_command| with_movement(AutoMovement::SetYaw(yaw), console_command);
+        let strafe = with_movement(
+            AutoMovement::Strafe(StrafeSettings {
+                type_: hltas::types::StrafeType::MaxAccel,
+                dir: hltas::types::StrafeDir::Left,
+            }),
+            "",
+        );
+
+        let mut lines = vec![
+            yaw(10., "bxt_set_angles 5 10;cl_forwardspeed 400"),
+            Line::Comment(" comment".to_owned()),
+            yaw(300., "bxt_set_angles_x 1 2"),
+            strafe.clone(),
+        ];
+        assert_eq!(rotate_yaws(&mut lines, 90.), 2);
+
+        assert_eq!(
+            lines,
+            [
+                yaw(100., "bxt_set_angles 5 100;cl_forwardspeed 400"),
+                Line::Comment(" comment".to_owned()),
+                // Wraps around.
+                yaw(30., "bxt_set_angles_x 1 2"),
+                strafe,
+            ]
+        );
+    }
+}